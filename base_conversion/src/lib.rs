@@ -8,6 +8,22 @@ pub fn lookup_digits_mod_at_position(x: u8, q: u16, pos: usize) -> &'static [u16
     }
 }
 
+/// The whole table for modulus `q` at byte position `pos`: the digits for each of the 256
+/// byte values in turn, `num_digits_at_position(q, pos)` apiece.
+pub fn lookup_table_at_position(q: u16, pos: usize) -> &'static [u16] {
+    unsafe {
+        let tab = c_get_table(q, pos);
+        let len = c_num_digits(q, pos);
+        std::slice::from_raw_parts(tab, 256*len)
+    }
+}
+
+pub fn num_digits_at_position(q: u16, pos: usize) -> usize {
+    unsafe {
+        c_num_digits(q, pos)
+    }
+}
+
 pub fn lookup_defined_for_mod(q: u16) -> bool {
     unsafe {
         c_num_digits(q, 0) > 0
@@ -55,15 +55,34 @@ fn half_gate(q: u16) -> Circuit {
     b.finish()
 }
 
+// many independent chains of multiplications, built layer by layer so that consecutive
+// gates belong to different chains
+fn wide(q: u16) -> Circuit {
+    let mut b = Builder::new();
+    let mut xs = b.inputs(256, q);
+    let ys = b.inputs(256, q);
+    for _ in 0..16 {
+        xs = xs.iter().zip(ys.iter()).map(|(&x,&y)| b.half_gate(x,y)).collect();
+    }
+    b.outputs(&xs);
+    b.finish()
+}
+
+fn wide_reordered(q: u16) -> Circuit {
+    wide(q).reorder_for_locality().0
+}
+
 fn proj17_gb(c: &mut Criterion) { bench_garble(c,"proj",proj,17) }
 fn proj17_ev(c: &mut Criterion) { bench_eval(c,"proj",proj,17) }
 fn mul_gb(c: &mut Criterion) { bench_garble(c,"mul",half_gate,17) }
 fn mul_ev(c: &mut Criterion) { bench_eval(c,"mul",half_gate,17) }
+fn wide_gb(c: &mut Criterion) { bench_garble(c,"wide",wide,17) }
+fn wide_reordered_gb(c: &mut Criterion) { bench_garble(c,"wide_reordered",wide_reordered,17) }
 
 criterion_group!{
     name = garbling;
     config = Criterion::default().warm_up_time(Duration::from_millis(100));
-    targets = proj17_gb, proj17_ev, mul_gb, mul_ev
+    targets = proj17_gb, proj17_ev, mul_gb, mul_ev, wide_gb, wide_reordered_gb
 }
 
 criterion_main!(garbling);
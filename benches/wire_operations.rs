@@ -14,6 +14,28 @@ fn bench_unpack(c: &mut Criterion, p: u16) {
     });
 }
 
+fn bench_unpack_many(c: &mut Criterion, p: u16) {
+    c.bench_function(&format!("wire::unpack_many{}", p), move |b| {
+        let rng = &mut rand::thread_rng();
+        let xs = (0..10000).map(|_| rng.gen_u128()).collect::<Vec<_>>();
+        b.iter(|| {
+            let ws = xs.iter().map(|&x| Wire::from_u128(x, p)).collect::<Vec<_>>();
+            criterion::black_box(ws);
+        });
+    });
+}
+
+fn bench_batch_unpack(c: &mut Criterion, p: u16) {
+    c.bench_function(&format!("wire::batch_unpack{}", p), move |b| {
+        let rng = &mut rand::thread_rng();
+        let xs = (0..10000).map(|_| rng.gen_u128()).collect::<Vec<_>>();
+        b.iter(|| {
+            let ws = Wire::batch_from_u128(&xs, p);
+            criterion::black_box(ws);
+        });
+    });
+}
+
 fn bench_pack(c: &mut Criterion, p: u16) {
     c.bench_function(&format!("wire::pack{}", p), move |b| {
         let rng = &mut rand::thread_rng();
@@ -105,6 +127,8 @@ fn bench_zero(c: &mut Criterion, p: u16) {
 
 
 fn unpack17(c: &mut Criterion) { bench_unpack(c,17) }
+fn unpackmany17(c: &mut Criterion) { bench_unpack_many(c,17) }
+fn batchunpack17(c: &mut Criterion) { bench_batch_unpack(c,17) }
 fn pack17(c: &mut Criterion) { bench_pack(c,17) }
 fn plus17(c: &mut Criterion) { bench_plus(c,17) }
 fn pluseq17(c: &mut Criterion) { bench_plus_eq(c,17) }
@@ -117,7 +141,7 @@ fn zero17(c: &mut Criterion) { bench_zero(c,17) }
 criterion_group!{
     name = wire_conversion;
     config = Criterion::default().warm_up_time(Duration::from_millis(100));
-    targets = unpack17, pack17, plus17, pluseq17, cmul17, cmuleq17, negate17, hash17, zero17
+    targets = unpack17, unpackmany17, batchunpack17, pack17, plus17, pluseq17, cmul17, cmuleq17, negate17, hash17, zero17
 }
 
 criterion_main!(wire_conversion);
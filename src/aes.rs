@@ -1,4 +1,11 @@
 use crate::util;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The tweakable hash functions used to garble and evaluate gates.
+pub trait Cipher {
+    fn hash(&self, t: u128, x: u128) -> u128;
+    fn hash2(&self, t: u128, x: u128, y: u128) -> u128;
+}
 
 pub struct Aes {
     round_keys: [u8; 176],
@@ -71,6 +78,105 @@ impl Aes {
     }
 }
 
+impl Cipher for Aes {
+    fn hash(&self, t: u128, x: u128) -> u128 {
+        Aes::hash(self, t, x)
+    }
+
+    fn hash2(&self, t: u128, x: u128, y: u128) -> u128 {
+        Aes::hash2(self, t, x, y)
+    }
+}
+
+impl<C: Cipher + ?Sized> Cipher for &C {
+    fn hash(&self, t: u128, x: u128) -> u128 {
+        (**self).hash(t, x)
+    }
+
+    fn hash2(&self, t: u128, x: u128, y: u128) -> u128 {
+        (**self).hash2(t, x, y)
+    }
+}
+
+/// Wraps a `Cipher`, counting the number of calls to `hash` and `hash2`. Useful for
+/// measuring exactly how much work garbling or evaluating a circuit takes.
+pub struct CountingCipher<C: Cipher = Aes> {
+    inner: C,
+    count: AtomicUsize,
+}
+
+impl CountingCipher<Aes> {
+    /// Count calls to the default `AES` cipher.
+    pub fn new() -> Self {
+        Self::wrap(AES)
+    }
+}
+
+impl<C: Cipher> CountingCipher<C> {
+    pub fn wrap(inner: C) -> Self {
+        CountingCipher { inner, count: AtomicUsize::new(0) }
+    }
+
+    /// The number of hashes computed so far.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn reset(&self) {
+        self.count.store(0, Ordering::SeqCst);
+    }
+}
+
+impl<C: Cipher> Cipher for CountingCipher<C> {
+    fn hash(&self, t: u128, x: u128) -> u128 {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.inner.hash(t, x)
+    }
+
+    fn hash2(&self, t: u128, x: u128, y: u128) -> u128 {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.inner.hash2(t, x, y)
+    }
+}
+
+/// Wraps a `Cipher`, adding `base` to every tweak, so that sessions using different bases
+/// hash with different tweaks even when they share a key.
+pub struct TweakOffset<C: Cipher = Aes> {
+    inner: C,
+    base: u128,
+}
+
+impl TweakOffset<Aes> {
+    /// Offset the tweaks of the default `AES` cipher.
+    pub fn new(base: u128) -> Self {
+        Self::wrap(AES, base)
+    }
+}
+
+impl<C: Cipher> TweakOffset<C> {
+    pub fn wrap(inner: C, base: u128) -> Self {
+        TweakOffset { inner, base }
+    }
+
+    pub fn base(&self) -> u128 {
+        self.base
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C: Cipher> Cipher for TweakOffset<C> {
+    fn hash(&self, t: u128, x: u128) -> u128 {
+        self.inner.hash(t.wrapping_add(self.base), x)
+    }
+
+    fn hash2(&self, t: u128, x: u128, y: u128) -> u128 {
+        self.inner.hash2(t.wrapping_add(self.base), x, y)
+    }
+}
+
 extern {
     fn aesni_setup_round_key_128(key: *const u8, round_key: *mut u8);
     fn aesni_encrypt_block(rounds: u8, input: *const u8, round_keys: *const u8, output: *mut u8);
@@ -117,4 +223,14 @@ mod tests {
         let out = aes.eval_u128(util::bytes_to_u128(inp));
         assert_eq!(out, util::bytes_to_u128(should_be));
     }
+
+    #[test]
+    fn counting_cipher() {
+        let cipher = CountingCipher::new();
+        assert_eq!(Cipher::hash(&cipher, 1, 2), AES.hash(1, 2));
+        assert_eq!(Cipher::hash2(&cipher, 1, 2, 3), AES.hash2(1, 2, 3));
+        assert_eq!(cipher.count(), 2);
+        cipher.reset();
+        assert_eq!(cipher.count(), 0);
+    }
 }
@@ -0,0 +1,400 @@
+//! A compact binary encoding of circuits, much smaller than serde formats for large
+//! circuits.
+//!
+//! Moduli and truth tables are stored once in tables. Each gate is a tag byte holding its
+//! type and, when small enough, its index in the moduli table, followed by varints: the
+//! distance back to each argument, the index of its truth table if it has one, and the
+//! difference between its id and the id expected if ids are assigned in order. Typical
+//! gates take 3-5 bytes.
+
+use crate::circuit::{Circuit, Gate, OutputMode};
+use itertools::Itertools;
+use std::collections::HashMap;
+
+const INPUT: u8    = 0;
+const CONST: u8    = 1;
+const ADD: u8      = 2;
+const SUB: u8      = 3;
+const CMUL: u8     = 4;
+const PROJ: u8     = 5;
+const YAO: u8      = 6;
+const HALFGATE: u8 = 7;
+
+// moduli table indices below this fit in the tag byte, and are stored offset by one
+const TAG_MODULI: usize = 31;
+
+impl Circuit {
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut w = Vec::new();
+
+        let moduli = self.gate_moduli.iter().cloned().unique().collect_vec();
+        put(&mut w, moduli.len() as u64);
+        for &q in moduli.iter() {
+            put(&mut w, q as u64);
+        }
+
+        let (projs, yaos) = self.truth_tables();
+        put(&mut w, projs.len() as u64);
+        for tt in projs.iter() {
+            put(&mut w, tt.len() as u64);
+            for &v in tt.iter() {
+                put(&mut w, v as u64);
+            }
+        }
+        put(&mut w, yaos.len() as u64);
+        for tt in yaos.iter() {
+            put(&mut w, tt.len() as u64);
+            put(&mut w, tt.first().map_or(0, |row| row.len()) as u64);
+            for &v in tt.iter().flatten() {
+                put(&mut w, v as u64);
+            }
+        }
+        let proj_index: HashMap<&[u16], usize> = projs.iter().enumerate().map(|(i, &tt)| (tt, i)).collect();
+        let yao_index: HashMap<&[Vec<u16>], usize> = yaos.iter().enumerate().map(|(i, &tt)| (tt, i)).collect();
+
+        put(&mut w, self.gates.len() as u64);
+        let (mut ninputs, mut nconsts, mut nciphertexts) = (0, 0, 0);
+        for (r, g) in self.gates.iter().enumerate() {
+            let (tag, args) = match *g {
+                Gate::Input { .. }              => (INPUT, vec![]),
+                Gate::Const { .. }              => (CONST, vec![]),
+                Gate::Add { xref, yref }        => (ADD, vec![xref, yref]),
+                Gate::Sub { xref, yref }        => (SUB, vec![xref, yref]),
+                Gate::Cmul { xref, .. }         => (CMUL, vec![xref]),
+                Gate::Proj { xref, .. }         => (PROJ, vec![xref]),
+                Gate::Yao { xref, yref, .. }    => (YAO, vec![xref, yref]),
+                Gate::HalfGate { xref, yref, .. } => (HALFGATE, vec![xref, yref]),
+            };
+            let mi = moduli.iter().position(|&q| q == self.gate_moduli[r]).unwrap();
+            if mi < TAG_MODULI {
+                w.push(tag | ((mi as u8 + 1) << 3));
+            } else {
+                w.push(tag);
+                put(&mut w, mi as u64);
+            }
+            for x in args {
+                put(&mut w, (r - x) as u64);
+            }
+            match *g {
+                Gate::Input { id } => { put_offset(&mut w, id, ninputs); ninputs += 1; }
+                Gate::Const { id } => { put_offset(&mut w, id, nconsts); nconsts += 1; }
+                Gate::Cmul { c, .. } => put(&mut w, c as u64),
+                Gate::Proj { ref tt, id, .. } => {
+                    put(&mut w, proj_index[&tt[..]] as u64);
+                    put_offset(&mut w, id, nciphertexts);
+                    nciphertexts += 1;
+                }
+                Gate::Yao { ref tt, id, .. } => {
+                    put(&mut w, yao_index[&tt[..]] as u64);
+                    put_offset(&mut w, id, nciphertexts);
+                    nciphertexts += 1;
+                }
+                Gate::HalfGate { id, .. } => { put_offset(&mut w, id, nciphertexts); nciphertexts += 1; }
+                _ => (),
+            }
+        }
+
+        for refs in [&self.input_refs, &self.const_refs, &self.output_refs].iter() {
+            put(&mut w, refs.len() as u64);
+            for &r in refs.iter() {
+                put(&mut w, r as u64);
+            }
+        }
+        match self.const_vals {
+            None => w.push(0),
+            Some(ref cs) => {
+                w.push(1);
+                put(&mut w, cs.len() as u64);
+                for &c in cs.iter() {
+                    put(&mut w, c as u64);
+                }
+            }
+        }
+        put(&mut w, self.num_nonfree_gates as u64);
+        put(&mut w, self.output_modes.len() as u64);
+        for &m in self.output_modes.iter() {
+            w.push((m == OutputMode::Labeled) as u8);
+        }
+        put(&mut w, self.output_names.len() as u64);
+        for (name, &i) in self.output_names.iter().sorted_by_key(|&(_, &i)| i) {
+            put(&mut w, name.len() as u64);
+            w.extend_from_slice(name.as_bytes());
+            put(&mut w, i as u64);
+        }
+        w
+    }
+
+    pub fn from_compact_bytes(bs: &[u8]) -> Result<Circuit, failure::Error> {
+        let mut r = Reader { bs, pos: 0 };
+
+        let nmoduli = r.usize()?;
+        let mut moduli = Vec::new();
+        for _ in 0..nmoduli {
+            moduli.push(r.u16()?);
+        }
+
+        let nprojs = r.usize()?;
+        let mut projs: Vec<Vec<u16>> = Vec::new();
+        for _ in 0..nprojs {
+            let n = r.usize()?;
+            projs.push((0..n).map(|_| r.u16()).collect::<Result<_,_>>()?);
+        }
+        let nyaos = r.usize()?;
+        let mut yaos: Vec<Vec<Vec<u16>>> = Vec::new();
+        for _ in 0..nyaos {
+            let nrows = r.usize()?;
+            let ncols = r.usize()?;
+            yaos.push((0..nrows).map(|_| {
+                (0..ncols).map(|_| r.u16()).collect::<Result<_,_>>()
+            }).collect::<Result<_,_>>()?);
+        }
+
+        let ngates = r.usize()?;
+        let mut gates = Vec::new();
+        let mut gate_moduli = Vec::new();
+        let (mut ninputs, mut nconsts, mut nciphertexts) = (0, 0, 0);
+        for i in 0..ngates {
+            let tag = r.byte()?;
+            let mi = match (tag >> 3) as usize {
+                0 => r.usize()?,
+                m => m - 1,
+            };
+            let q = *moduli.get(mi).ok_or_else(|| {
+                failure::format_err!("[from_compact_bytes] gate {} has bad modulus index {}", i, mi)
+            })?;
+            let gate = match tag & 7 {
+                INPUT => {
+                    let id = r.offset(ninputs)?;
+                    ninputs += 1;
+                    Gate::Input { id }
+                }
+                CONST => {
+                    let id = r.offset(nconsts)?;
+                    nconsts += 1;
+                    Gate::Const { id }
+                }
+                ADD => Gate::Add { xref: r.arg(i)?, yref: r.arg(i)? },
+                SUB => Gate::Sub { xref: r.arg(i)?, yref: r.arg(i)? },
+                CMUL => Gate::Cmul { xref: r.arg(i)?, c: r.u32()? },
+                PROJ => {
+                    let xref = r.arg(i)?;
+                    let tt = projs.get(r.usize()?)
+                        .filter(|tt| tt.len() == gate_moduli[xref] as usize)
+                        .ok_or_else(|| failure::format_err!("[from_compact_bytes] gate {} has a bad truth table", i))?
+                        .clone();
+                    let id = r.offset(nciphertexts)?;
+                    nciphertexts += 1;
+                    Gate::Proj { xref, tt, id }
+                }
+                YAO => {
+                    let xref = r.arg(i)?;
+                    let yref = r.arg(i)?;
+                    let tt = yaos.get(r.usize()?)
+                        .filter(|tt| tt.len() == gate_moduli[xref] as usize &&
+                                     tt.iter().all(|row| row.len() == gate_moduli[yref] as usize))
+                        .ok_or_else(|| failure::format_err!("[from_compact_bytes] gate {} has a bad truth table", i))?
+                        .clone();
+                    let id = r.offset(nciphertexts)?;
+                    nciphertexts += 1;
+                    Gate::Yao { xref, yref, tt, id }
+                }
+                _ => {
+                    let xref = r.arg(i)?;
+                    let yref = r.arg(i)?;
+                    let id = r.offset(nciphertexts)?;
+                    nciphertexts += 1;
+                    Gate::HalfGate { xref, yref, id }
+                }
+            };
+            gates.push(gate);
+            gate_moduli.push(q);
+        }
+
+        let mut refs = Vec::new();
+        for _ in 0..3 {
+            let n = r.usize()?;
+            let rs = (0..n).map(|_| r.usize()).collect::<Result<Vec<_>,_>>()?;
+            if let Some(&bad) = rs.iter().find(|&&x| x >= ngates) {
+                return Err(failure::format_err!("[from_compact_bytes] ref {} out of range", bad));
+            }
+            refs.push(rs);
+        }
+        let const_vals = match r.byte()? {
+            0 => None,
+            _ => {
+                let n = r.usize()?;
+                Some((0..n).map(|_| r.u16()).collect::<Result<_,_>>()?)
+            }
+        };
+        let num_nonfree_gates = r.usize()?;
+        let nmodes = r.usize()?;
+        let mut output_modes = Vec::new();
+        for _ in 0..nmodes {
+            output_modes.push(if r.byte()? == 0 { OutputMode::Decoded } else { OutputMode::Labeled });
+        }
+        let nnames = r.usize()?;
+        let mut output_names = HashMap::new();
+        for _ in 0..nnames {
+            let len = r.usize()?;
+            let name = (0..len).map(|_| r.byte()).collect::<Result<Vec<_>,_>>()?;
+            let name = String::from_utf8(name)
+                .map_err(|_| failure::err_msg("[from_compact_bytes] output name is not utf-8"))?;
+            output_names.insert(name, r.usize()?);
+        }
+        if r.pos != bs.len() {
+            return Err(failure::err_msg("[from_compact_bytes] trailing bytes"));
+        }
+
+        let output_refs = refs.pop().unwrap();
+        let const_refs = refs.pop().unwrap();
+        let input_refs = refs.pop().unwrap();
+        Ok(Circuit { gates, gate_moduli, input_refs, const_refs, output_refs, const_vals,
+                     num_nonfree_gates, output_modes, output_names })
+    }
+}
+
+// LEB128
+fn put(w: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        w.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    w.push(x as u8);
+}
+
+// zigzag encoding of the difference between an id and the expected one
+fn put_offset(w: &mut Vec<u8>, id: usize, expected: usize) {
+    let d = id as i64 - expected as i64;
+    put(w, ((d << 1) ^ (d >> 63)) as u64);
+}
+
+struct Reader<'a> {
+    bs: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, failure::Error> {
+        let b = *self.bs.get(self.pos)
+            .ok_or_else(|| failure::err_msg("[from_compact_bytes] unexpected end of input"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u64(&mut self) -> Result<u64, failure::Error> {
+        let mut x = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            if shift >= 64 {
+                return Err(failure::err_msg("[from_compact_bytes] varint too long"));
+            }
+            x |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(x);
+            }
+            shift += 7;
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize, failure::Error> {
+        Ok(self.u64()? as usize)
+    }
+
+    fn u16(&mut self) -> Result<u16, failure::Error> {
+        let x = self.u64()?;
+        if x > u16::MAX as u64 {
+            return Err(failure::format_err!("[from_compact_bytes] {} does not fit in a u16", x));
+        }
+        Ok(x as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, failure::Error> {
+        let x = self.u64()?;
+        if x > u32::MAX as u64 {
+            return Err(failure::format_err!("[from_compact_bytes] {} does not fit in a u32", x));
+        }
+        Ok(x as u32)
+    }
+
+    // an argument of gate i, stored as its distance back from i
+    fn arg(&mut self, i: usize) -> Result<usize, failure::Error> {
+        let d = self.usize()?;
+        if d == 0 || d > i {
+            return Err(failure::format_err!("[from_compact_bytes] gate {} has a bad argument", i));
+        }
+        Ok(i - d)
+    }
+
+    fn offset(&mut self, expected: usize) -> Result<usize, failure::Error> {
+        let z = self.u64()?;
+        let d = ((z >> 1) as i64) ^ -((z & 1) as i64);
+        let id = expected as i64 + d;
+        if id < 0 {
+            return Err(failure::err_msg("[from_compact_bytes] negative id"));
+        }
+        Ok(id as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Builder, random_circuit};
+    use rand::thread_rng;
+
+    #[test] // compact_round_trip {{{
+    fn compact_round_trip() {
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let c = random_circuit(&mut rng, 4, 64, &[2,3,5,7,8,17,113]);
+            let bs = c.to_compact_bytes();
+            assert_eq!(Circuit::from_compact_bytes(&bs).unwrap(), c);
+            assert!(Circuit::from_compact_bytes(&bs[..bs.len()-1]).is_err());
+        }
+    }
+//}}}
+    #[test] // compact_large_circuit {{{
+    fn compact_large_circuit() {
+        let mut b = Builder::new();
+        let xs = b.inputs(64, 2);
+        let ys = b.inputs(64, 2);
+        let zs = b.binary_multiplication(&xs, &ys);
+        b.outputs(&zs);
+        let c = b.finish();
+        assert!(c.gates.len() > 10000);
+
+        let bs = c.to_compact_bytes();
+        assert_eq!(Circuit::from_compact_bytes(&bs).unwrap(), c);
+        let bincoded = bincode::serialize(&c).unwrap();
+        assert!(bs.len() * 4 < bincoded.len());
+        assert!(bs.len() < 6 * c.gates.len());
+    }
+//}}}
+    #[test] // compact_truth_tables {{{
+    fn compact_truth_tables() {
+        let mut b = Builder::new();
+        let xs = b.inputs(16, 113);
+        let tt = (0..113).map(|x| (x * x) % 113).collect_vec();
+        for _ in 0..16 {
+            for &x in xs.iter() {
+                let z = b.proj(x, 113, tt.clone());
+                let z = b.mod_change(z, 7);
+                b.output(z);
+            }
+        }
+        let c = b.finish();
+        let (projs, yaos) = c.truth_tables();
+        assert_eq!(projs.len(), 2);
+        assert_eq!(yaos.len(), 0);
+
+        let bs = c.to_compact_bytes();
+        let d = Circuit::from_compact_bytes(&bs).unwrap();
+        assert_eq!(d, c);
+        let inps = (0..16).map(|i| i * 7).collect_vec();
+        assert_eq!(d.eval(&inps), c.eval(&inps));
+        // each table of 113 entries is stored just once
+        assert!(bs.len() < 6 * c.gates.len() + 4 * 113);
+    }
+//}}}
+}
@@ -1,7 +1,16 @@
 //! DSL for creating circuits compatible with fancy-garbling.
 
 pub mod crt;
+mod compact;
+#[cfg(any(test, feature = "testing"))]
+mod random;
 
+#[cfg(any(test, feature = "testing"))]
+pub use self::random::random_circuit;
+
+use crate::numbers;
+use crate::util::RngExt;
+use crate::wire::Wire;
 use itertools::Itertools;
 use serde_derive::{Serialize, Deserialize};
 use std::collections::HashMap;
@@ -18,6 +27,9 @@ use std::collections::HashMap;
 pub type Ref = usize;
 pub type Id = usize;
 
+/// Identifies a class of structurally identical wires, see `Circuit::deduplicate_structure`.
+pub type SubcircuitId = usize;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Circuit {
     pub gates: Vec<Gate>,
@@ -27,20 +39,75 @@ pub struct Circuit {
     pub output_refs: Vec<Ref>,
     pub const_vals: Option<Vec<u16>>,
     pub num_nonfree_gates: usize,
+    #[serde(default)]
+    pub output_modes: Vec<OutputMode>,
+    /// The index of each output named with `Builder::output_named`.
+    #[serde(default)]
+    pub output_names: HashMap<String, usize>,
 }
 
+/// Machine-readable statistics about a circuit, see `Circuit::stats`.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CircuitStats {
+    pub ninputs: usize,
+    pub noutputs: usize,
+    pub nconsts: usize,
+    pub nadd: usize,
+    pub nsub: usize,
+    pub ncmul: usize,
+    pub nproj: usize,
+    pub nyao: usize,
+    pub nhalfgate: usize,
+    pub num_nonfree_gates: usize,
+    pub depth: usize,
+    pub width: usize,
+    pub free_gate_ratio: f64,
+    pub moduli: Vec<u16>,
+    pub ciphertexts: usize,
+}
+
+/// How the garbler treats an output wire.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OutputMode {
+    /// The garbler creates a table allowing the evaluator to decode the output.
+    Decoded,
+    /// The output is left as a wire label, for instance to be fed into another circuit.
+    Labeled,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Gate {
     Input { id: Id },                                           // id is the input id
     Const { id: Id },                                           // id is the const id
     Add { xref: Ref, yref: Ref },
     Sub { xref: Ref, yref: Ref },
-    Cmul { xref: Ref, c: u16 },
+    Cmul { xref: Ref, c: u32 },                                 // c is reduced mod q when evaluated
     Proj { xref: Ref, tt: Vec<u16>, id: Id },                   // id is the gate number
     Yao { xref: Ref, yref: Ref, tt: Vec<Vec<u16>>, id: Id },    // id is the gate number
     HalfGate { xref: Ref, yref: Ref, id: Id },                  // id is the gate number
 }
 
+impl Gate {
+    /// Whether garbling this gate is free, i.e. it produces no ciphertexts.
+    pub fn is_free(&self) -> bool {
+        match self {
+            Gate::Input { .. } | Gate::Const { .. } | Gate::Add { .. } |
+            Gate::Sub { .. } | Gate::Cmul { .. } => true,
+            Gate::Proj { .. } | Gate::Yao { .. } | Gate::HalfGate { .. } => false,
+        }
+    }
+
+    /// The wires this gate takes as arguments.
+    pub fn args(&self) -> Vec<Ref> {
+        match *self {
+            Gate::Input { .. } | Gate::Const { .. } => vec![],
+            Gate::Cmul { xref, .. } | Gate::Proj { xref, .. } => vec![xref],
+            Gate::Add { xref, yref } | Gate::Sub { xref, yref } |
+            Gate::Yao { xref, yref, .. } | Gate::HalfGate { xref, yref, .. } => vec![xref, yref],
+        }
+    }
+}
+
 impl Circuit {
     pub fn eval(&self, inputs: &[u16]) -> Vec<u16> {
         assert_eq!(inputs.len(), self.ninputs(),
@@ -64,7 +131,7 @@ impl Circuit {
                 Gate::Add { xref, yref } => (cache[xref] + cache[yref]) % q,
                 Gate::Sub { xref, yref } => (cache[xref] + q - cache[yref]) % q,
 
-                Gate::Cmul { xref, c } => cache[xref] * c % q,
+                Gate::Cmul { xref, c } => (cache[xref] as u32 * (c % q as u32) % q as u32) as u16,
 
                 Gate::Proj { xref, ref tt, .. } => tt[cache[xref] as usize],
 
@@ -81,6 +148,12 @@ impl Circuit {
 
     pub fn ninputs(&self) -> usize { self.input_refs.len() }
     pub fn noutputs(&self) -> usize { self.output_refs.len() }
+
+    /// The mode of output `i`. Circuits serialized before output modes existed decode
+    /// all of their outputs.
+    pub fn output_mode(&self, i: usize) -> OutputMode {
+        self.output_modes.get(i).cloned().unwrap_or(OutputMode::Decoded)
+    }
     pub fn modulus(&self, x: Ref) -> u16 { self.gate_moduli[x] }
 
     pub fn input_mod(&self, id: Id) -> u16 {
@@ -88,10 +161,539 @@ impl Circuit {
         self.gate_moduli[r]
     }
 
+    pub fn input_moduli(&self) -> Vec<u16> {
+        self.input_refs.iter().map(|&r| self.modulus(r)).collect()
+    }
+
+    pub fn output_moduli(&self) -> Vec<u16> {
+        self.output_refs.iter().map(|&r| self.modulus(r)).collect()
+    }
+
+    /// The ids of the inputs which output number `output` depends on, in ascending order.
+    pub fn output_dependencies(&self, output: usize) -> Vec<Id> {
+        let mut visited = vec![false; self.gates.len()];
+        let mut queue = std::collections::VecDeque::new();
+        let mut ids = Vec::new();
+        queue.push_back(self.output_refs[output]);
+        while let Some(r) = queue.pop_front() {
+            if visited[r] {
+                continue;
+            }
+            visited[r] = true;
+            if let Gate::Input { id } = self.gates[r] {
+                ids.push(id);
+            }
+            queue.extend(self.gates[r].args());
+        }
+        ids.sort();
+        ids
+    }
+
+    /// Structural analysis of repeated subcircuits. Two wires are given the same
+    /// `SubcircuitId` when they are computed by identical gates (same type, modulus, truth
+    /// table or scalar) from wires which themselves share a `SubcircuitId`. All inputs of a
+    /// given modulus are considered identical, as are all constants of a given modulus.
+    ///
+    /// Returns the circuit obtained by collapsing each class into a single gate, along with
+    /// the class of every gate of `self`. This is purely a description of the circuit's
+    /// shape: it is not a means of sharing wire labels between copies of a subcircuit, which
+    /// must always be garbled with fresh randomness, and the collapsed circuit does not
+    /// compute the same function as `self`.
+    pub fn deduplicate_structure(&self) -> (Circuit, Vec<SubcircuitId>) {
+        let mut classes: HashMap<(Gate, u16), SubcircuitId> = HashMap::new();
+        let mut ids = Vec::with_capacity(self.gates.len());
+        let mut circ = Circuit {
+            gates: Vec::new(),
+            gate_moduli: Vec::new(),
+            input_refs: Vec::new(),
+            const_refs: Vec::new(),
+            output_refs: Vec::new(),
+            const_vals: self.const_vals.as_ref().map(|_| Vec::new()),
+            num_nonfree_gates: 0,
+            output_modes: Vec::new(),
+            output_names: HashMap::new(),
+        };
+
+        for (r, gate) in self.gates.iter().enumerate() {
+            let q = self.modulus(r);
+            // the gate with its arguments replaced by their classes and its ids erased
+            let shape = match *gate {
+                Gate::Input { .. } => Gate::Input { id: 0 },
+                Gate::Const { .. } => Gate::Const { id: 0 },
+                Gate::Add { xref, yref } => Gate::Add { xref: ids[xref], yref: ids[yref] },
+                Gate::Sub { xref, yref } => Gate::Sub { xref: ids[xref], yref: ids[yref] },
+                Gate::Cmul { xref, c } => Gate::Cmul { xref: ids[xref], c },
+                Gate::Proj { xref, ref tt, .. } =>
+                    Gate::Proj { xref: ids[xref], tt: tt.clone(), id: 0 },
+                Gate::Yao { xref, yref, ref tt, .. } =>
+                    Gate::Yao { xref: ids[xref], yref: ids[yref], tt: tt.clone(), id: 0 },
+                Gate::HalfGate { xref, yref, .. } =>
+                    Gate::HalfGate { xref: ids[xref], yref: ids[yref], id: 0 },
+            };
+
+            if let Some(&class) = classes.get(&(shape.clone(), q)) {
+                ids.push(class);
+                continue;
+            }
+
+            let class = circ.gates.len();
+            let new_gate = match shape.clone() {
+                Gate::Input { .. } => {
+                    circ.input_refs.push(class);
+                    Gate::Input { id: circ.input_refs.len() - 1 }
+                }
+                Gate::Const { .. } => {
+                    if let (Some(cs), Gate::Const { id }) = (circ.const_vals.as_mut(), gate) {
+                        cs.push(self.const_vals.as_ref().unwrap()[*id]);
+                    }
+                    circ.const_refs.push(class);
+                    Gate::Const { id: circ.const_refs.len() - 1 }
+                }
+                Gate::Proj { xref, tt, .. } => {
+                    circ.num_nonfree_gates += 1;
+                    Gate::Proj { xref, tt, id: circ.num_nonfree_gates - 1 }
+                }
+                Gate::Yao { xref, yref, tt, .. } => {
+                    circ.num_nonfree_gates += 1;
+                    Gate::Yao { xref, yref, tt, id: circ.num_nonfree_gates - 1 }
+                }
+                Gate::HalfGate { xref, yref, .. } => {
+                    circ.num_nonfree_gates += 1;
+                    Gate::HalfGate { xref, yref, id: circ.num_nonfree_gates - 1 }
+                }
+                g => g,
+            };
+            classes.insert((shape, q), class);
+            circ.gates.push(new_gate);
+            circ.gate_moduli.push(q);
+            ids.push(class);
+        }
+
+        circ.output_refs = self.output_refs.iter().map(|&r| ids[r]).collect();
+        (circ, ids)
+    }
+
+    /// An equivalent circuit whose gates are ordered by a depth-first traversal from each
+    /// output in turn, so that gates feeding the same output sit close together, which
+    /// keeps wire accesses local while garbling wide circuits. Also returns the
+    /// permutation mapping each old `Ref` to its new one. Inputs, constants and outputs
+    /// keep their order, so encoders and decoders are used exactly as for `self`.
+    pub fn reorder_for_locality(&self) -> (Circuit, Vec<Ref>) {
+        let n = self.gates.len();
+        let mut order = Vec::with_capacity(n);
+        let mut visited = vec![false; n];
+        let roots = self.output_refs.iter().cloned().chain(0..n).collect_vec();
+        for root in roots {
+            // iterative post-order traversal, since circuits can be very deep
+            let mut stack = vec![(root, false)];
+            while let Some((r, expanded)) = stack.pop() {
+                if expanded {
+                    order.push(r);
+                    continue;
+                }
+                if visited[r] {
+                    continue;
+                }
+                visited[r] = true;
+                stack.push((r, true));
+                for x in self.gates[r].args().into_iter().rev() {
+                    if !visited[x] {
+                        stack.push((x, false));
+                    }
+                }
+            }
+        }
+
+        let mut perm = vec![0; n];
+        for (new, &old) in order.iter().enumerate() {
+            perm[old] = new;
+        }
+
+        let mut next_id = 0;
+        let mut fresh_id = || { next_id += 1; next_id - 1 };
+        let gates = order.iter().map(|&old| {
+            match self.gates[old] {
+                Gate::Add { xref, yref } => Gate::Add { xref: perm[xref], yref: perm[yref] },
+                Gate::Sub { xref, yref } => Gate::Sub { xref: perm[xref], yref: perm[yref] },
+                Gate::Cmul { xref, c } => Gate::Cmul { xref: perm[xref], c },
+                Gate::Proj { xref, ref tt, .. } =>
+                    Gate::Proj { xref: perm[xref], tt: tt.clone(), id: fresh_id() },
+                Gate::Yao { xref, yref, ref tt, .. } =>
+                    Gate::Yao { xref: perm[xref], yref: perm[yref], tt: tt.clone(), id: fresh_id() },
+                Gate::HalfGate { xref, yref, .. } =>
+                    Gate::HalfGate { xref: perm[xref], yref: perm[yref], id: fresh_id() },
+                ref g => g.clone(),
+            }
+        }).collect();
+
+        let circ = Circuit {
+            gates,
+            gate_moduli: order.iter().map(|&old| self.gate_moduli[old]).collect(),
+            input_refs: self.input_refs.iter().map(|&r| perm[r]).collect(),
+            const_refs: self.const_refs.iter().map(|&r| perm[r]).collect(),
+            output_refs: self.output_refs.iter().map(|&r| perm[r]).collect(),
+            const_vals: self.const_vals.clone(),
+            num_nonfree_gates: self.num_nonfree_gates,
+            output_modes: self.output_modes.clone(),
+            output_names: self.output_names.clone(),
+        };
+        (circ, perm)
+    }
+
+    /// An equivalent circuit with each projection of a projection fused into one. When the
+    /// input of `Proj(x, tt2)` is `Proj(y, tt1)` and that inner wire is used nowhere else,
+    /// the pair becomes `Proj(y, |i| tt2[tt1[i]])`, saving the inner gate's ciphertexts.
+    /// Chains are fused entirely. Inputs, constants and outputs keep their order.
+    pub fn simplify(&self) -> Circuit {
+        let n = self.gates.len();
+        let mut uses = vec![0; n];
+        for r in self.gates.iter().flat_map(Gate::args).chain(self.output_refs.iter().cloned()) {
+            uses[r] += 1;
+        }
+
+        let mut gates = self.gates.clone();
+        let mut removed = vec![false; n];
+        for r in 0..n {
+            let fused = match gates[r] {
+                Gate::Proj { xref, ref tt, .. } if uses[xref] == 1 => match gates[xref] {
+                    Gate::Proj { xref: inner, tt: ref inner_tt, .. } if tt.len() == self.modulus(xref) as usize =>
+                        Some((xref, inner, inner_tt.iter().map(|&i| tt[i as usize]).collect_vec())),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some((dead, inner, tt)) = fused {
+                removed[dead] = true;
+                gates[r] = Gate::Proj { xref: inner, tt, id: 0 };
+            }
+        }
+
+        let mut new_refs = vec![0; n];
+        for (new, r) in (0..n).filter(|&r| !removed[r]).enumerate() {
+            new_refs[r] = new;
+        }
+
+        let mut next_id = 0;
+        let mut fresh_id = || { next_id += 1; next_id - 1 };
+        let gates = gates.into_iter().enumerate().filter(|&(r, _)| !removed[r]).map(|(_, g)| {
+            match g {
+                Gate::Add { xref, yref } => Gate::Add { xref: new_refs[xref], yref: new_refs[yref] },
+                Gate::Sub { xref, yref } => Gate::Sub { xref: new_refs[xref], yref: new_refs[yref] },
+                Gate::Cmul { xref, c } => Gate::Cmul { xref: new_refs[xref], c },
+                Gate::Proj { xref, tt, .. } => Gate::Proj { xref: new_refs[xref], tt, id: fresh_id() },
+                Gate::Yao { xref, yref, tt, .. } =>
+                    Gate::Yao { xref: new_refs[xref], yref: new_refs[yref], tt, id: fresh_id() },
+                Gate::HalfGate { xref, yref, .. } =>
+                    Gate::HalfGate { xref: new_refs[xref], yref: new_refs[yref], id: fresh_id() },
+                g => g,
+            }
+        }).collect();
+
+        Circuit {
+            gates,
+            gate_moduli: (0..n).filter(|&r| !removed[r]).map(|r| self.gate_moduli[r]).collect(),
+            input_refs: self.input_refs.iter().map(|&r| new_refs[r]).collect(),
+            const_refs: self.const_refs.iter().map(|&r| new_refs[r]).collect(),
+            output_refs: self.output_refs.iter().map(|&r| new_refs[r]).collect(),
+            const_vals: self.const_vals.clone(),
+            num_nonfree_gates: self.num_nonfree_gates - removed.iter().filter(|&&x| x).count(),
+            output_modes: self.output_modes.clone(),
+            output_names: self.output_names.clone(),
+        }
+    }
+
+    /// The wires before gate `at` which are still needed from there on, either as the
+    /// argument of a later gate or as an output, in increasing order. These are the wires
+    /// passed between segments by `Garbler::garble_segment`.
+    pub fn segment_boundary(&self, at: Ref) -> Vec<Ref> {
+        let mut needed = vec![false; at.min(self.gates.len())];
+        let later = self.gates.iter().skip(at).flat_map(Gate::args);
+        for r in later.chain(self.output_refs.iter().cloned()) {
+            if r < needed.len() {
+                needed[r] = true;
+            }
+        }
+        (0..needed.len()).filter(|&r| needed[r]).collect()
+    }
+
+    /// Test whether `other` computes the same function by evaluating both on `samples`
+    /// random inputs. Circuits whose input or output moduli differ are never equivalent.
+    /// A `true` result is evidence, not proof, of equivalence.
+    pub fn probably_equivalent<R: rand::Rng>(&self, other: &Circuit, samples: usize, rng: &mut R) -> bool {
+        if self.input_moduli() != other.input_moduli() || self.output_moduli() != other.output_moduli() {
+            return false;
+        }
+        (0..samples).all(|_| {
+            let inps = self.input_moduli().iter().map(|&q| rng.gen_u16() % q).collect_vec();
+            self.eval(&inps) == other.eval(&inps)
+        })
+    }
+
+    /// The smallest circuit computing the value of wire `root` as its only output. Its
+    /// inputs are the inputs of `self` which `root` depends on, in the order of their ids,
+    /// so the inputs for `self.subcircuit(r)` are those at the ids listed by
+    /// `output_dependencies` for an output at `r`.
+    pub fn subcircuit(&self, root: Ref) -> Circuit {
+        let mut needed = vec![false; root+1];
+        needed[root] = true;
+        for r in (0..=root).rev() {
+            if needed[r] {
+                for x in self.gates[r].args() {
+                    needed[x] = true;
+                }
+            }
+        }
+        let input_ids = (0..=root).filter_map(|r| match self.gates[r] {
+            Gate::Input { id } if needed[r] => Some(id),
+            _ => None,
+        }).sorted().collect_vec();
+
+        let mut circ = Circuit {
+            gates: Vec::new(),
+            gate_moduli: Vec::new(),
+            input_refs: vec![0; input_ids.len()],
+            const_refs: Vec::new(),
+            output_refs: Vec::new(),
+            const_vals: self.const_vals.as_ref().map(|_| Vec::new()),
+            num_nonfree_gates: 0,
+            output_modes: Vec::new(),
+            output_names: HashMap::new(),
+        };
+        let mut new_refs = vec![0; root+1];
+        for r in (0..=root).filter(|&r| needed[r]) {
+            let new = circ.gates.len();
+            let gate = match self.gates[r] {
+                Gate::Input { id } => {
+                    let id = input_ids.binary_search(&id).unwrap();
+                    circ.input_refs[id] = new;
+                    Gate::Input { id }
+                }
+                Gate::Const { id } => {
+                    if let (Some(cs), Some(vals)) = (circ.const_vals.as_mut(), self.const_vals.as_ref()) {
+                        cs.push(vals[id]);
+                    }
+                    circ.const_refs.push(new);
+                    Gate::Const { id: circ.const_refs.len() - 1 }
+                }
+                Gate::Add { xref, yref } => Gate::Add { xref: new_refs[xref], yref: new_refs[yref] },
+                Gate::Sub { xref, yref } => Gate::Sub { xref: new_refs[xref], yref: new_refs[yref] },
+                Gate::Cmul { xref, c } => Gate::Cmul { xref: new_refs[xref], c },
+                Gate::Proj { xref, ref tt, .. } => {
+                    circ.num_nonfree_gates += 1;
+                    Gate::Proj { xref: new_refs[xref], tt: tt.clone(), id: circ.num_nonfree_gates - 1 }
+                }
+                Gate::Yao { xref, yref, ref tt, .. } => {
+                    circ.num_nonfree_gates += 1;
+                    Gate::Yao { xref: new_refs[xref], yref: new_refs[yref], tt: tt.clone(),
+                                id: circ.num_nonfree_gates - 1 }
+                }
+                Gate::HalfGate { xref, yref, .. } => {
+                    circ.num_nonfree_gates += 1;
+                    Gate::HalfGate { xref: new_refs[xref], yref: new_refs[yref], id: circ.num_nonfree_gates - 1 }
+                }
+            };
+            circ.gates.push(gate);
+            circ.gate_moduli.push(self.modulus(r));
+            new_refs[r] = new;
+        }
+        circ.output_refs.push(new_refs[root]);
+        circ.output_modes.push(OutputMode::Decoded);
+        circ
+    }
+
+    /// Append dummy identity projections, whose outputs are never used, until the circuit
+    /// has `target_nonfree` non-free gates. They are garbled and sent like any other gate,
+    /// so this hides the true size of the circuit from anyone observing the garbling. It
+    /// serves no other purpose and does not change the outputs.
+    pub fn pad_to(&self, target_nonfree: usize) -> Circuit {
+        assert!(target_nonfree >= self.num_nonfree_gates,
+            "[circuit.pad_to] circuit already has {} non-free gates", self.num_nonfree_gates);
+        assert!(!self.gates.is_empty(), "[circuit.pad_to] cannot pad an empty circuit");
+        let mut c = self.clone();
+        let mut x = 0;
+        let q = c.modulus(x);
+        while c.num_nonfree_gates < target_nonfree {
+            c.gates.push(Gate::Proj { xref: x, tt: (0..q).collect(), id: c.num_nonfree_gates });
+            c.gate_moduli.push(q);
+            c.num_nonfree_gates += 1;
+            x = c.gates.len() - 1;
+        }
+        c
+    }
+
+    /// Specialize the circuit by turning each input `i` in `fixed` into a constant with the
+    /// paired value. The new constants come after the existing ones, and the remaining
+    /// inputs keep their relative order. The circuit must have its constant values.
+    pub fn fix_inputs(&self, fixed: &[(usize, u16)]) -> Circuit {
+        let mut vals = HashMap::new();
+        for &(i, v) in fixed.iter() {
+            assert!(i < self.ninputs(), "[circuit.fix_inputs] no input {}", i);
+            assert!(v < self.input_mod(i), "[circuit.fix_inputs] value {} too large for input {}", v, i);
+            assert!(vals.insert(i, v).is_none(), "[circuit.fix_inputs] input {} fixed twice", i);
+        }
+        let mut c = self.clone();
+        let consts = c.const_vals.as_mut().expect("[circuit.fix_inputs] constant values were cleared");
+
+        let mut input_refs = Vec::new();
+        for r in 0..c.gates.len() {
+            if let Gate::Input { id } = c.gates[r] {
+                c.gates[r] = match vals.get(&id) {
+                    Some(&v) => {
+                        consts.push(v);
+                        c.const_refs.push(r);
+                        Gate::Const { id: c.const_refs.len() - 1 }
+                    }
+                    None => {
+                        input_refs.push((id, r));
+                        Gate::Input { id }
+                    }
+                };
+            }
+        }
+        input_refs.sort();
+        for (new_id, &(_, r)) in input_refs.iter().enumerate() {
+            c.gates[r] = Gate::Input { id: new_id };
+        }
+        c.input_refs = input_refs.into_iter().map(|(_, r)| r).collect();
+        c
+    }
+
     pub fn clear_consts(&mut self) {
         self.const_vals = None;
     }
 
+    /// The fraction of gates which are free to garble (inputs, constants, additions,
+    /// subtractions and scalar multiplications). An empty circuit is entirely free.
+    pub fn free_gate_ratio(&self) -> f64 {
+        if self.gates.is_empty() {
+            return 1.0;
+        }
+        let nfree = self.gates.iter().filter(|g| g.is_free()).count();
+        nfree as f64 / self.gates.len() as f64
+    }
+
+    /// An estimate, in bytes, of the memory needed to garble this circuit: the labels of
+    /// every wire plus the ciphertexts of the garbled gates and outputs.
+    pub fn estimated_garble_memory(&self) -> usize {
+        let label = |q: u16| {
+            let digits = if q == 2 { 0 } else { numbers::digits_per_u128(q) };
+            std::mem::size_of::<Wire>() + digits * std::mem::size_of::<u16>()
+        };
+        let cts = |n: usize| std::mem::size_of::<Vec<u128>>() + n * std::mem::size_of::<u128>();
+
+        let mut total = 0;
+        for (g, &q) in self.gates.iter().zip(self.gate_moduli.iter()) {
+            total += label(q);
+            total += match *g {
+                Gate::Input { .. } | Gate::Const { .. } => label(q), // held by the encoder too
+                _ if g.is_free() => 0,
+                _ => cts(self.ciphertexts(g)),
+            };
+        }
+        for (i, &r) in self.output_refs.iter().enumerate() {
+            if self.output_mode(i) == OutputMode::Decoded {
+                total += cts(self.modulus(r) as usize);
+            }
+        }
+        total
+    }
+
+    /// The distinct truth tables of the projection gates and of the yao gates, each listed
+    /// once in order of first use. Circuits often apply the same few tables at many gates,
+    /// so `to_compact_bytes` stores these once and has gates refer to them by index.
+    pub fn truth_tables(&self) -> (Vec<&[u16]>, Vec<&[Vec<u16>]>) {
+        let projs = self.gates.iter().filter_map(|g| match g {
+            Gate::Proj { tt, .. } => Some(&tt[..]),
+            _ => None,
+        }).unique().collect();
+        let yaos = self.gates.iter().filter_map(|g| match g {
+            Gate::Yao { tt, .. } => Some(&tt[..]),
+            _ => None,
+        }).unique().collect();
+        (projs, yaos)
+    }
+
+    /// The number of ciphertexts the garbler creates for the gate `g` of this circuit.
+    pub fn ciphertexts(&self, g: &Gate) -> usize {
+        match *g {
+            Gate::Proj { xref, .. } => self.modulus(xref) as usize - 1,
+            Gate::Yao { xref, yref, .. } => self.modulus(xref) as usize * self.modulus(yref) as usize - 1,
+            Gate::HalfGate { xref, yref, .. } => {
+                let (qa, qb) = (self.modulus(xref) as usize, self.modulus(yref) as usize);
+                qa + qb - 2 + (qa != qb) as usize
+            }
+            _ => 0,
+        }
+    }
+
+    /// The largest number of hashes along any path to an output, where each gate costs the
+    /// hashes the evaluator must compute in sequence for it: none for free gates, one for
+    /// projections and yao gates, and two for half gates. This models the latency of
+    /// garbling or evaluating with unlimited parallelism better than plain depth.
+    pub fn critical_hash_path(&self) -> usize {
+        let mut costs: Vec<usize> = Vec::with_capacity(self.gates.len());
+        for g in self.gates.iter() {
+            let w = match g {
+                Gate::Proj { .. } | Gate::Yao { .. } => 1,
+                Gate::HalfGate { .. } => 2,
+                _ => 0,
+            };
+            costs.push(w + g.args().iter().map(|&x| costs[x]).max().unwrap_or(0));
+        }
+        self.output_refs.iter().map(|&r| costs[r]).max().unwrap_or(0)
+    }
+
+    /// Gate counts, depth, width, moduli and garbled size of the circuit. Depth is the
+    /// length of the longest path from an input or constant, and width the largest number
+    /// of gates at any one depth. The garbled size counts the ciphertexts of both the
+    /// garbled gates and the output decoding tables.
+    pub fn stats(&self) -> CircuitStats {
+        let mut stats = CircuitStats {
+            ninputs: self.ninputs(),
+            noutputs: self.noutputs(),
+            nconsts: 0, nadd: 0, nsub: 0, ncmul: 0, nproj: 0, nyao: 0, nhalfgate: 0,
+            num_nonfree_gates: self.num_nonfree_gates,
+            depth: 0,
+            width: 0,
+            free_gate_ratio: self.free_gate_ratio(),
+            moduli: self.gate_moduli.iter().cloned().unique().sorted().collect(),
+            ciphertexts: 0,
+        };
+
+        let mut levels = Vec::with_capacity(self.gates.len());
+        let mut level_counts = HashMap::new();
+        for g in self.gates.iter() {
+            match g {
+                Gate::Input    { .. } => (),
+                Gate::Const    { .. } => stats.nconsts   += 1,
+                Gate::Add      { .. } => stats.nadd      += 1,
+                Gate::Sub      { .. } => stats.nsub      += 1,
+                Gate::Cmul     { .. } => stats.ncmul     += 1,
+                Gate::Proj     { .. } => stats.nproj     += 1,
+                Gate::Yao      { .. } => stats.nyao      += 1,
+                Gate::HalfGate { .. } => stats.nhalfgate += 1,
+            }
+            stats.ciphertexts += self.ciphertexts(g);
+            let level = g.args().iter().map(|&x| levels[x] + 1).max().unwrap_or(0);
+            levels.push(level);
+            *level_counts.entry(level).or_insert(0) += 1;
+        }
+        stats.depth = levels.iter().cloned().max().unwrap_or(0);
+        stats.width = level_counts.values().cloned().max().unwrap_or(0);
+
+        for (i, &r) in self.output_refs.iter().enumerate() {
+            if self.output_mode(i) == OutputMode::Decoded {
+                stats.ciphertexts += self.modulus(r) as usize;
+            }
+        }
+        stats
+    }
+
+    /// The output of `stats` as a JSON object.
+    pub fn stats_json(&self) -> String {
+        serde_json::to_string(&self.stats()).expect("couldn't serialize circuit stats")
+    }
+
     pub fn print_info(&self) {
         let mut nconst = 0;
         let mut nadd = 0;
@@ -154,11 +756,82 @@ impl Circuit {
     }
 }
 
+/// The ways in which a `Builder` can refuse to add to a circuit.
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    /// A yao gate would need `size` ciphertexts, more than the limit set with
+    /// `Builder::with_max_yao_size`.
+    YaoTooLarge { size: usize },
+    /// A wire was requested with a `modulus` less than 2.
+    SmallModulus { modulus: u16 },
+    /// An output was given a name already used by another output.
+    DuplicateOutputName { name: String },
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::YaoTooLarge { size } =>
+                write!(f, "yao gate needs {} ciphertexts, consider decomposing it into half gates", size),
+            BuildError::SmallModulus { modulus } =>
+                write!(f, "modulus must be at least 2, got {}", modulus),
+            BuildError::DuplicateOutputName { name } =>
+                write!(f, "there is already an output named {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// The operation used by `Builder::fold`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FoldOp {
+    /// Addition, wrapping around modulo the capacity of the accumulator.
+    Add,
+    Max,
+    Min,
+}
+
+/// A secret floating point number for `Builder::float_mul`, whose value is
+/// `(-1)^sign * mantissa * 2^(exponent - mantissa.len() + 1)`. All wires are mod 2. The
+/// mantissa is an unsigned binary number whose top bit must be set, and the exponent is a
+/// two's complement binary number. There is no zero, infinity or NaN.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Float {
+    pub sign: Ref,
+    pub exponent: Vec<Ref>,
+    pub mantissa: Vec<Ref>,
+}
+
+// The compare-exchanges of Batcher's odd-even merge sorting network on n elements, in order.
+fn batcher_network(n: usize) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut p = 1;
+    while p < n {
+        let mut k = p;
+        while k >= 1 {
+            let mut j = k % p;
+            while j + k < n {
+                for i in 0..k.min(n-j-k) {
+                    if (i+j) / (2*p) == (i+j+k) / (2*p) {
+                        pairs.push((i+j, i+j+k));
+                    }
+                }
+                j += 2*k;
+            }
+            k /= 2;
+        }
+        p *= 2;
+    }
+    pairs
+}
+
 // Use a Builder to conveniently make a Circuit
 pub struct Builder {
     next_ref: Ref,
     next_input_id: Id,
     const_map: HashMap<(u16,u16), Ref>,
+    max_yao_size: usize,
     pub circ: Circuit,
 }
 
@@ -172,15 +845,25 @@ impl Builder {
             gate_moduli: Vec::new(),
             const_vals: Some(Vec::new()),
             num_nonfree_gates: 0,
+            output_modes: Vec::new(),
+            output_names: HashMap::new(),
         };
         Builder {
             next_ref: 0,
             next_input_id: 0,
             const_map: HashMap::new(),
+            max_yao_size: usize::MAX,
             circ: c
         }
     }
 
+    /// Refuse to create yao gates needing more than `size` ciphertexts: `try_yao` fails
+    /// with `BuildError::YaoTooLarge`, and `yao` panics. There is no limit by default.
+    pub fn with_max_yao_size(mut self, size: usize) -> Self {
+        self.max_yao_size = size;
+        self
+    }
+
     pub fn finish(self) -> Circuit {
         self.circ
     }
@@ -211,25 +894,76 @@ impl Builder {
         x
     }
 
+    fn check_modulus(method: &str, modulus: u16) {
+        assert!(modulus >= 2, "[builder.{}] modulus must be at least 2, got {}", method, modulus);
+    }
+
     fn gate(&mut self, gate: Gate, modulus: u16) -> Ref {
         self.circ.gates.push(gate);
         self.circ.gate_moduli.push(modulus);
         self.get_next_ref()
     }
 
+    /// Splice a copy of the prebuilt circuit `sub` into this one, feeding `inputs` to its
+    /// inputs, and return the refs of its outputs. Each input must have the modulus of the
+    /// corresponding input of `sub`, whose constants must not have been cleared.
+    pub fn embed(&mut self, sub: &Circuit, inputs: &[Ref]) -> Vec<Ref> {
+        assert_eq!(inputs.len(), sub.ninputs(), "[builder.embed] expected {} inputs", sub.ninputs());
+        for (i, &x) in inputs.iter().enumerate() {
+            assert_eq!(self.modulus(x), sub.input_mod(i), "[builder.embed] input {} has the wrong modulus", i);
+        }
+        let consts = sub.const_vals.as_ref().expect("[builder.embed] constant values were cleared");
+        let mut new_refs = Vec::with_capacity(sub.gates.len());
+        for (r, g) in sub.gates.iter().enumerate() {
+            let q = sub.modulus(r);
+            let new = match *g {
+                Gate::Input { id } => inputs[id],
+                Gate::Const { id } => self.constant(consts[id], q),
+                Gate::Add { xref, yref } => self.gate(Gate::Add { xref: new_refs[xref], yref: new_refs[yref] }, q),
+                Gate::Sub { xref, yref } => self.gate(Gate::Sub { xref: new_refs[xref], yref: new_refs[yref] }, q),
+                Gate::Cmul { xref, c } => self.gate(Gate::Cmul { xref: new_refs[xref], c }, q),
+                Gate::Proj { xref, ref tt, .. } => {
+                    let id = self.get_next_ciphertext_id();
+                    self.gate(Gate::Proj { xref: new_refs[xref], tt: tt.clone(), id }, q)
+                }
+                Gate::Yao { xref, yref, ref tt, .. } => {
+                    let id = self.get_next_ciphertext_id();
+                    self.gate(Gate::Yao { xref: new_refs[xref], yref: new_refs[yref], tt: tt.clone(), id }, q)
+                }
+                Gate::HalfGate { xref, yref, .. } => {
+                    let id = self.get_next_ciphertext_id();
+                    self.gate(Gate::HalfGate { xref: new_refs[xref], yref: new_refs[yref], id }, q)
+                }
+            };
+            new_refs.push(new);
+        }
+        sub.output_refs.iter().map(|&r| new_refs[r]).collect()
+    }
+
     pub fn input(&mut self, modulus: u16) -> Ref {
+        self.try_input(modulus).unwrap_or_else(|e| panic!("[builder.input] {}", e))
+    }
+
+    /// Like `input`, but fails with `BuildError::SmallModulus` rather than panicking if
+    /// `modulus` is less than 2.
+    pub fn try_input(&mut self, modulus: u16) -> Result<Ref, BuildError> {
+        if modulus < 2 {
+            return Err(BuildError::SmallModulus { modulus });
+        }
         let gate = Gate::Input { id: self.get_next_input_id() };
         let r = self.gate(gate, modulus);
         self.circ.input_refs.push(r);
-        r
+        Ok(r)
     }
 
     pub fn inputs(&mut self, n: usize, modulus: u16) -> Vec<Ref> {
+        Self::check_modulus("inputs", modulus);
         (0..n).map(|_| self.input(modulus)).collect()
     }
 
     /// creates a new, secret, constant for each call
     pub fn secret_constant(&mut self, val: u16, modulus: u16) -> Ref {
+        Self::check_modulus("secret_constant", modulus);
         let id = self.circ.const_vals.as_ref().map_or(0, |cs| cs.len());
         if let Some(cs) = self.circ.const_vals.as_mut() { cs.push(val) }
         let gate = Gate::Const { id };
@@ -240,6 +974,7 @@ impl Builder {
 
     /// reuses constants if they already exist in the circuit
     pub fn constant(&mut self, val: u16, modulus: u16) -> Ref {
+        Self::check_modulus("constant", modulus);
         match self.const_map.get(&(val, modulus)) {
             Some(&r) => r,
             None => {
@@ -256,6 +991,29 @@ impl Builder {
 
     pub fn output(&mut self, xref: Ref) {
         self.circ.output_refs.push(xref);
+        self.circ.output_modes.push(OutputMode::Decoded);
+    }
+
+    /// Output `xref` under `name`, which `Decoder::decode_named` uses to report its value.
+    /// Panics if the name is taken, see `try_output_named`.
+    pub fn output_named(&mut self, xref: Ref, name: &str) {
+        self.try_output_named(xref, name).unwrap_or_else(|e| panic!("[builder.output_named] {}", e))
+    }
+
+    /// Like `output_named`, but fails if another output already has this name.
+    pub fn try_output_named(&mut self, xref: Ref, name: &str) -> Result<(), BuildError> {
+        if self.circ.output_names.contains_key(name) {
+            return Err(BuildError::DuplicateOutputName { name: name.to_string() });
+        }
+        self.circ.output_names.insert(name.to_string(), self.circ.output_refs.len());
+        self.output(xref);
+        Ok(())
+    }
+
+    /// Output `xref` as a wire label, without a decoding table.
+    pub fn output_labeled(&mut self, xref: Ref) {
+        self.circ.output_refs.push(xref);
+        self.circ.output_modes.push(OutputMode::Labeled);
     }
 
     pub fn outputs(&mut self, xs: &[Ref]) {
@@ -269,7 +1027,7 @@ impl Builder {
         assert!(yref < self.next_ref);
         let xmod = self.circ.gate_moduli[xref];
         let ymod = self.circ.gate_moduli[yref];
-        assert!(xmod == ymod, "xmod={} ymod={}", xmod, ymod);
+        assert!(xmod == ymod, "add: modulus mismatch {} vs {}", xmod, ymod);
         let gate = Gate::Add { xref, yref };
         self.gate(gate, xmod)
     }
@@ -279,18 +1037,29 @@ impl Builder {
         assert!(yref < self.next_ref);
         let xmod = self.circ.gate_moduli[xref];
         let ymod = self.circ.gate_moduli[yref];
-        assert!(xmod == ymod);
+        assert!(xmod == ymod, "sub: modulus mismatch {} vs {}", xmod, ymod);
         let gate = Gate::Sub { xref, yref };
         self.gate(gate, xmod)
     }
 
     pub fn cmul(&mut self, xref: Ref, c: u16) -> Ref {
+        self.cmul_wide(xref, c as u32)
+    }
+
+    /// Multiply by a constant which may exceed the range of a u16. It is reduced modulo
+    /// the modulus of `xref`.
+    pub fn cmul_wide(&mut self, xref: Ref, c: u32) -> Ref {
         let q = self.modulus(xref);
         self.gate(Gate::Cmul { xref, c }, q)
     }
 
     pub fn add_many(&mut self, args: &[Ref]) -> Ref {
         assert!(args.len() > 1);
+        let q = self.modulus(args[0]);
+        for &x in args.iter().skip(1) {
+            let p = self.modulus(x);
+            assert!(p == q, "add_many: modulus mismatch {} vs {}", q, p);
+        }
         let mut z = args[0];
         for &x in args.iter().skip(1) {
             z = self.add(z, x);
@@ -299,6 +1068,7 @@ impl Builder {
     }
 
     pub fn proj(&mut self, xref: Ref, output_modulus: u16, tt: Vec<u16>) -> Ref {
+        Self::check_modulus("proj", output_modulus);
         assert_eq!(tt.len(), self.circ.gate_moduli[xref] as usize);
         assert!(tt.iter().all(|&x| x < output_modulus),
             "not all xs were less than the output modulus! circuit.proj: tt={:?},
@@ -310,14 +1080,39 @@ impl Builder {
 
     // the classic yao binary gate, over mixed moduli!
     pub fn yao(&mut self, xref: Ref, yref: Ref, output_modulus: u16, tt: Vec<Vec<u16>>) -> Ref {
+        self.try_yao(xref, yref, output_modulus, tt).unwrap_or_else(|e| panic!("[builder.yao] {}", e))
+    }
+
+    /// Like `yao`, but fails if the gate is larger than the limit set with
+    /// `with_max_yao_size`.
+    pub fn try_yao(&mut self, xref: Ref, yref: Ref, output_modulus: u16, tt: Vec<Vec<u16>>)
+        -> Result<Ref, BuildError>
+    {
+        Self::check_modulus("yao", output_modulus);
         assert!(tt.iter().all(|ref inner| { inner.iter().all(|&x| x < output_modulus) }));
+        let size = self.modulus(xref) as usize * self.modulus(yref) as usize - 1;
+        if size > self.max_yao_size {
+            return Err(BuildError::YaoTooLarge { size });
+        }
         let gate = Gate::Yao {
             xref,
             yref,
             tt,
             id: self.get_next_ciphertext_id()
         };
-        self.gate(gate, output_modulus)
+        Ok(self.gate(gate, output_modulus))
+    }
+
+    /// Computes `x mod d` for a secret divisor `d`, with the convention that `x mod 0 = 0`.
+    /// This is a single yao gate, which costs `xmod * dmod - 1` ciphertexts, so it is only
+    /// practical for small moduli. `q_out` must be large enough to hold every remainder.
+    pub fn mod_secret(&mut self, x: Ref, d: Ref, q_out: u16) -> Ref {
+        let xmod = self.modulus(x);
+        let dmod = self.modulus(d);
+        let tt = (0..xmod).map(|a| {
+            (0..dmod).map(|b| if b == 0 { 0 } else { a % b }).collect()
+        }).collect();
+        self.yao(x, d, q_out, tt)
     }
 
     pub fn half_gate(&mut self, xref: Ref, yref: Ref) -> Ref {
@@ -389,39 +1184,329 @@ impl Builder {
         self.proj(z, 2, tab)
     }
 
-    pub fn mod_change(&mut self, xref: Ref, to_modulus: u16) -> Ref {
-        let from_modulus = self.modulus(xref);
-        if from_modulus == to_modulus {
-            return xref;
+    fn check_bitwise(&self, method: &str, xs: &[Ref], ys: &[Ref]) {
+        assert_eq!(xs.len(), ys.len(), "[builder.{}] bundles must have equal length", method);
+        assert!(xs.iter().chain(ys.iter()).all(|&x| self.modulus(x) == 2),
+            "[builder.{}] expected binary inputs", method);
+    }
+
+    pub fn bitwise_xor(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        self.check_bitwise("bitwise_xor", xs, ys);
+        xs.iter().zip(ys.iter()).map(|(&x,&y)| self.xor(x,y)).collect()
+    }
+
+    pub fn bitwise_and(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        self.check_bitwise("bitwise_and", xs, ys);
+        xs.iter().zip(ys.iter()).map(|(&x,&y)| self.and(x,y)).collect()
+    }
+
+    pub fn bitwise_or(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        self.check_bitwise("bitwise_or", xs, ys);
+        // x | y = !(!x & !y) = x ^ y ^ (x & y), which avoids projecting to negate
+        xs.iter().zip(ys.iter()).map(|(&x,&y)| {
+            let z = self.xor(x,y);
+            let w = self.and(x,y);
+            self.xor(z,w)
+        }).collect()
+    }
+
+    /// The XOR of the low bits of the values of `xs`, as a mod-2 wire. Free when all of
+    /// `xs` are mod 2, otherwise each other wire costs a projection.
+    pub fn parity(&mut self, xs: &[Ref]) -> Ref {
+        let bits = xs.iter().map(|&x| {
+            let q = self.modulus(x);
+            if q == 2 {
+                x
+            } else {
+                self.proj(x, 2, (0..q).map(|v| v % 2).collect())
+            }
+        }).collect_vec();
+        match bits.len() {
+            0 => self.constant(0,2),
+            _ => bits[1..].iter().fold(bits[0], |z, &b| self.add(z,b)),
         }
-        let tab = (0..from_modulus).map(|x| x % to_modulus).collect();
-        self.proj(xref, to_modulus, tab)
     }
 
-    ////////////////////////////////////////////////////////////////////////////////
-    // mixed radix stuff
+    /// Returns `x` if the mod-2 wire `sel` is 0, and `y` if it is 1.
+    pub fn mux(&mut self, sel: Ref, x: Ref, y: Ref) -> Ref {
+        assert_eq!(self.modulus(sel), 2, "[builder.mux] selector must be mod 2");
+        let d = self.sub(y,x);
+        let z = self.half_gate(d,sel);
+        self.add(x,z)
+    }
 
-    pub fn fancy_addition(&mut self, xs: &[Vec<Ref>]) -> Vec<Ref> {
-        let nargs = xs.len();
-        let n = xs[0].len();
-        assert!(xs.iter().all(|x| x.len() == n));
+    /// Select `options[i]`, where `i` is the binary number `sel_bits`, least significant
+    /// bit first. There must be exactly `2^sel_bits.len()` options, all with the same
+    /// moduli. Built as a balanced tree of `mux`es consuming one selector bit per level, so
+    /// it costs one half gate per wire of every option but one.
+    pub fn mux_tree(&mut self, sel_bits: &[Ref], options: &[Vec<Ref>]) -> Vec<Ref> {
+        assert_eq!(options.len(), 1 << sel_bits.len(), "[builder.mux_tree] need 2^{} options", sel_bits.len());
+        let mut level = options.to_vec();
+        for &s in sel_bits.iter() {
+            level = level.chunks(2).map(|pair| {
+                assert_eq!(pair[0].len(), pair[1].len(), "[builder.mux_tree] options must have equal length");
+                pair[0].iter().zip(pair[1].iter()).map(|(&x,&y)| self.mux(s, x, y)).collect()
+            }).collect();
+        }
+        level.pop().unwrap()
+    }
 
-        let mut digit_carry = None;
-        let mut carry_carry = None;
+    /// Returns `(xs, ys)` if the mod-2 wire `sel` is 0, and `(ys, xs)` if it is 1.
+    pub fn cond_swap(&mut self, sel: Ref, xs: &[Ref], ys: &[Ref]) -> (Vec<Ref>, Vec<Ref>) {
+        assert_eq!(xs.len(), ys.len(), "[builder.cond_swap] bundles must have equal length");
+        xs.iter().zip(ys.iter()).map(|(&x,&y)| {
+            assert_eq!(self.modulus(x), self.modulus(y), "[builder.cond_swap] modulus mismatch");
+            let a = self.mux(sel, x, y);
+            // the other output is x + y - a, which saves a second multiplication
+            let s = self.add(x,y);
+            let b = self.sub(s,a);
+            (a,b)
+        }).unzip()
+    }
 
-        let mut max_carry = 0;
+    /// The number of the mod-2 wires `xs` which are set, as a wire with modulus
+    /// `xs.len() + 1`.
+    pub fn popcount(&mut self, xs: &[Ref]) -> Ref {
+        assert!(!xs.is_empty(), "[builder.popcount] no inputs");
+        assert!(xs.iter().all(|&x| self.modulus(x) == 2), "[builder.popcount] expected binary inputs");
+        let n = xs.len() as u16;
+        let wires = xs.iter().map(|&x| self.mod_change(x, n+1)).collect_vec();
+        if wires.len() == 1 {
+            wires[0]
+        } else {
+            self.add_many(&wires)
+        }
+    }
 
-        let mut res = Vec::with_capacity(n);
+    /// The number of positions where the equal-length mod-2 bundles `xs` and `ys` differ,
+    /// as a one-wire bundle of modulus `xs.len()+1`.
+    pub fn hamming_distance(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        let diffs = self.bitwise_xor(xs, ys);
+        vec![self.popcount(&diffs)]
+    }
 
-        for i in 0..n {
-            // all the ith digits, in one vec
-            let ds = xs.iter().map(|x| x[i]).collect_vec();
+    /// Whether at least `k` of the mod-2 wires `xs` are set, as a mod-2 wire.
+    pub fn at_least_k(&mut self, xs: &[Ref], k: usize) -> Ref {
+        let count = self.popcount(xs);
+        let tt = (0..=xs.len()).map(|v| (v >= k) as u16).collect();
+        self.proj(count, 2, tt)
+    }
 
-            // compute the digit -- easy
-            let digit_sum = self.add_many(&ds);
-            let digit = digit_carry.map_or(digit_sum, |d| self.add(digit_sum, d));
+    /// One-hot encoding of `index`: one mod-2 wire for each possible value of `index`,
+    /// exactly one of which is 1.
+    pub fn one_hot(&mut self, index: Ref) -> Vec<Ref> {
+        let q = self.modulus(index);
+        (0..q).map(|i| {
+            let tt = (0..q).map(|v| (v == i) as u16).collect();
+            self.proj(index, 2, tt)
+        }).collect()
+    }
 
-            if i < n-1 {
+    /// The inverse of `one_hot`: the index of the set wire among the mod-2 wires `bits`, as
+    /// a wire of modulus `bits.len()`. Each bit is changed to that modulus with a
+    /// projection and the rest is free. If `bits` is not actually one-hot, the result is
+    /// the sum of the indices of the set wires, mod `bits.len()`.
+    pub fn from_one_hot(&mut self, bits: &[Ref]) -> Ref {
+        let n = bits.len();
+        assert!(n >= 2, "[builder.from_one_hot] need at least two wires");
+        assert!(n <= u16::MAX as usize, "[builder.from_one_hot] too many wires");
+        let terms = bits.iter().enumerate().map(|(i, &b)| {
+            let x = self.mod_change(b, n as u16);
+            self.cmul(x, i as u16)
+        }).collect_vec();
+        self.add_many(&terms)
+    }
+
+    /// Compute `x^e mod p` for a secret exponent `e` of small modulus, by square and
+    /// conditional multiply over the bits of `e`. For an exponent modulus `m` this costs
+    /// `ceil(log2(m))` projections and about three half gates per bit: one to `mux` each
+    /// factor, one to multiply it in, and one to square. Note that `0^0 = 1`.
+    pub fn pow_secret(&mut self, x: Ref, e: Ref, p: u16) -> Ref {
+        assert_eq!(self.modulus(x), p, "[builder.pow_secret] x must have modulus {}", p);
+        let m = self.modulus(e);
+        let nbits = (m as f64).log2().ceil() as usize;
+        let one = self.constant(1,p);
+        let mut base = x;
+        let mut acc = None;
+        for j in 0..nbits {
+            let tt = (0..m).map(|v| (v >> j) & 1).collect();
+            let bit = self.proj(e, 2, tt);
+            let factor = self.mux(bit, one, base);
+            acc = Some(match acc {
+                None => factor,
+                Some(z) => self.half_gate(z, factor),
+            });
+            if j + 1 < nbits {
+                base = self.half_gate(base, base);
+            }
+        }
+        acc.unwrap()
+    }
+
+    /// Evaluate the polynomial with secret coefficients `coeffs`, highest degree first, at
+    /// the secret point `x` by Horner's method, with everything mod `p`. Each step computes
+    /// `acc*x + c` with one half gate and a free addition.
+    pub fn horner(&mut self, coeffs: &[Ref], x: Ref, p: u16) -> Ref {
+        assert!(!coeffs.is_empty(), "[builder.horner] no coefficients");
+        assert!(coeffs.iter().chain(std::iter::once(&x)).all(|&c| self.modulus(c) == p),
+            "[builder.horner] all wires must have modulus {}", p);
+        coeffs[1..].iter().fold(coeffs[0], |acc, &c| {
+            let z = self.half_gate(acc, x);
+            self.add(z, c)
+        })
+    }
+
+    /// Look up `table[index]` where both the index and the table entries are secret. The
+    /// modulus of `index` must be the length of the table, and all entries share a modulus.
+    /// Costs one projection and one half gate per table entry.
+    pub fn secret_lookup(&mut self, index: Ref, table: &[Ref]) -> Ref {
+        assert_eq!(self.modulus(index) as usize, table.len(),
+            "[builder.secret_lookup] index modulus must equal the table length");
+        let q = self.modulus(table[0]);
+        assert!(table.iter().all(|&t| self.modulus(t) == q),
+            "[builder.secret_lookup] all table entries must have modulus {}", q);
+        let bits = self.one_hot(index);
+        let zs = bits.iter().zip(table.iter()).map(|(&b,&t)| self.half_gate(t,b)).collect_vec();
+        self.add_many(&zs)
+    }
+
+    /// Read `array[idx]` where `idx` is secret. The modulus of `idx` must be the length of
+    /// the array and all bundles must share moduli. This is `secret_lookup` applied to each
+    /// wire position with a single one-hot encoding of `idx` shared between them.
+    pub fn oblivious_read(&mut self, array: &[Vec<Ref>], idx: Ref) -> Vec<Ref> {
+        assert_eq!(self.modulus(idx) as usize, array.len(),
+            "[builder.oblivious_read] index modulus must equal the array length");
+        let bits = self.one_hot(idx);
+        (0..array[0].len()).map(|j| {
+            let zs = bits.iter().zip(array.iter()).map(|(&b, xs)| {
+                assert_eq!(xs.len(), array[0].len(), "[builder.oblivious_read] bundles must have equal length");
+                assert_eq!(self.modulus(xs[j]), self.modulus(array[0][j]), "[builder.oblivious_read] modulus mismatch");
+                self.half_gate(xs[j], b)
+            }).collect_vec();
+            self.add_many(&zs)
+        }).collect()
+    }
+
+    /// Overwrite `array[idx]` with `value` where `idx` is secret, returning the whole updated
+    /// array. The modulus of `idx` must be the length of the array, and `value` must have the
+    /// same moduli as every bundle in it. Costs one projection per slot to one-hot encode
+    /// `idx` and one half gate per wire of the array to `mux` in the new value.
+    pub fn oblivious_write(&mut self, array: &[Vec<Ref>], idx: Ref, value: &[Ref]) -> Vec<Vec<Ref>> {
+        assert_eq!(self.modulus(idx) as usize, array.len(),
+            "[builder.oblivious_write] index modulus must equal the array length");
+        let bits = self.one_hot(idx);
+        bits.iter().zip(array.iter()).map(|(&b, xs)| {
+            assert_eq!(xs.len(), value.len(), "[builder.oblivious_write] bundles must have equal length");
+            xs.iter().zip(value.iter()).map(|(&x,&v)| {
+                assert_eq!(self.modulus(x), self.modulus(v), "[builder.oblivious_write] modulus mismatch");
+                self.mux(b, x, v)
+            }).collect()
+        }).collect()
+    }
+
+    /// The sum of `terms`, all of which must have modulus `p`, followed by a single identity
+    /// projection. The additions are free, and the projection gives the sum a fresh wire of
+    /// its own rather than one that is a linear combination of the terms' wires.
+    pub fn sum_mod_many(&mut self, terms: &[Ref], p: u16) -> Ref {
+        assert!(!terms.is_empty(), "[builder.sum_mod_many] no terms");
+        assert!(terms.iter().all(|&x| self.modulus(x) == p),
+            "[builder.sum_mod_many] all terms must have modulus {}", p);
+        let z = if terms.len() == 1 { terms[0] } else { self.add_many(terms) };
+        self.proj(z, p, (0..p).collect())
+    }
+
+    /// The sum of `weights[i] * xs[i] mod p`, where all of `xs` have modulus `p` and the
+    /// weights are public. Unlike `dot`, which multiplies secret by secret, this is free.
+    pub fn weighted_sum(&mut self, xs: &[Ref], weights: &[u16], p: u16) -> Ref {
+        assert_eq!(xs.len(), weights.len(), "[builder.weighted_sum] unequal lengths");
+        assert!(!xs.is_empty(), "[builder.weighted_sum] empty vectors");
+        assert!(xs.iter().all(|&x| self.modulus(x) == p),
+            "[builder.weighted_sum] all wires must have modulus {}", p);
+        let zs = xs.iter().zip(weights.iter()).map(|(&x,&w)| self.cmul(x,w)).collect_vec();
+        if zs.len() == 1 {
+            zs[0]
+        } else {
+            self.add_many(&zs)
+        }
+    }
+
+    /// One step of an exponential moving average, `alpha*sample + (1-alpha)*prev` for the
+    /// public rational `alpha = alpha_num/alpha_den`, applied to each pair of wires of `prev`
+    /// and `sample`, all of which have modulus `p`. Both wires are moved to a modulus large
+    /// enough to hold `alpha_num*sample + (alpha_den-alpha_num)*prev` exactly, which is then
+    /// computed with free `cmul`s and addition, and a projection divides it by `alpha_den`.
+    ///
+    /// The division rounds down, so each step is less than one below the exact average, and
+    /// over a stream of updates the result stays less than `alpha_den/alpha_num` below it.
+    /// Each wire costs two `mod_change`s and a projection from a modulus of
+    /// `alpha_den*(p-1)+1`, which must be at most 2^15.
+    pub fn ema_update(&mut self, prev: &[Ref], sample: &[Ref], alpha_num: u16, alpha_den: u16, p: u16) -> Vec<Ref> {
+        assert!(alpha_num <= alpha_den && alpha_den > 0, "[builder.ema_update] alpha must be in [0,1]");
+        assert_eq!(prev.len(), sample.len(), "[builder.ema_update] bundles must have equal length");
+        assert!(prev.iter().chain(sample.iter()).all(|&x| self.modulus(x) == p),
+            "[builder.ema_update] all wires must have modulus {}", p);
+        let q = alpha_den as u32 * (p as u32 - 1) + 1;
+        assert!(q <= 1 << 15, "[builder.ema_update] alpha_den * (p-1) is too large");
+        let q = q as u16;
+        let tt = (0..q).map(|v| v / alpha_den).collect_vec();
+        prev.iter().zip(sample.iter()).map(|(&x,&s)| {
+            let xq = self.mod_change(x, q);
+            let sq = self.mod_change(s, q);
+            let a = self.cmul(sq, alpha_num);
+            let b = self.cmul(xq, alpha_den - alpha_num);
+            let z = self.add(a, b);
+            self.proj(z, p, tt.clone())
+        }).collect()
+    }
+
+    /// Inner product of `xs` and `ys`, all of which must have modulus `p`.
+    pub fn dot(&mut self, xs: &[Ref], ys: &[Ref], p: u16) -> Ref {
+        assert_eq!(xs.len(), ys.len(), "[builder.dot] unequal lengths");
+        assert!(!xs.is_empty(), "[builder.dot] empty vectors");
+        assert!(xs.iter().chain(ys.iter()).all(|&x| self.modulus(x) == p),
+            "[builder.dot] all wires must have modulus {}", p);
+        let zs = xs.iter().zip(ys.iter()).map(|(&x,&y)| self.half_gate(x,y)).collect_vec();
+        if zs.len() == 1 {
+            zs[0]
+        } else {
+            self.add_many(&zs)
+        }
+    }
+
+    pub fn mod_change(&mut self, xref: Ref, to_modulus: u16) -> Ref {
+        Self::check_modulus("mod_change", to_modulus);
+        let from_modulus = self.modulus(xref);
+        if from_modulus == to_modulus {
+            return xref;
+        }
+        let tab = (0..from_modulus).map(|x| x % to_modulus).collect();
+        self.proj(xref, to_modulus, tab)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // mixed radix stuff
+
+    pub fn fancy_addition(&mut self, xs: &[Vec<Ref>]) -> Vec<Ref> {
+        let nargs = xs.len();
+        let n = xs[0].len();
+        assert!(xs.iter().all(|x| x.len() == n));
+
+        let mut digit_carry = None;
+        let mut carry_carry = None;
+
+        let mut max_carry = 0;
+
+        let mut res = Vec::with_capacity(n);
+
+        for i in 0..n {
+            // all the ith digits, in one vec
+            let ds = xs.iter().map(|x| x[i]).collect_vec();
+
+            // compute the digit -- easy
+            let digit_sum = self.add_many(&ds);
+            let digit = digit_carry.map_or(digit_sum, |d| self.add(digit_sum, d));
+
+            if i < n-1 {
                 // compute the carries
                 let q = self.modulus(xs[0][i]);
                 // max_carry currently contains the max carry from the previous iteration
@@ -470,7 +1555,7 @@ impl Builder {
 
     pub fn addition(&mut self, xs: &[Ref], ys: &[Ref]) -> (Vec<Ref>, Ref) {
         assert_eq!(xs.len(), ys.len());
-        let cmod = self.modulus(xs[1]);
+        let cmod = self.modulus(*xs.get(1).unwrap_or(&xs[0]));
         let (mut z, mut c) = self.adder(xs[0], ys[0], None, cmod);
         let mut bs = vec![z];
         for i in 1..xs.len() {
@@ -483,9 +1568,25 @@ impl Builder {
         (bs, c)
     }
 
+    /// Add the public constant `by` to the base-q number `xs`. Returns the incremented
+    /// number, wrapped modulo `q^n`, and a mod-2 wire which is 1 iff the addition overflowed.
+    pub fn increment(&mut self, xs: &[Ref], by: u16) -> (Vec<Ref>, Ref) {
+        let q = self.modulus(xs[0]);
+        let ds = numbers::as_base_q(by as u128, q, xs.len());
+        assert_eq!(numbers::from_base_q(&ds, q), by as u128,
+            "[builder.increment] {} does not fit in {} base {} digits", by, xs.len(), q);
+        let ys = ds.into_iter().map(|d| self.constant(d,q)).collect_vec();
+        let (zs, c) = self.addition(xs, &ys);
+        let overflow = self.mod_change(c, 2);
+        (zs, overflow)
+    }
+
     // avoids creating extra gates for the final carry
     pub fn addition_no_carry(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
         assert_eq!(xs.len(), ys.len());
+        if xs.len() == 1 {
+            return vec![self.add(xs[0], ys[0])];
+        }
 
         let cmod = self.modulus(*xs.get(1).unwrap_or(&xs[0]));
         let (mut z, mut c) = self.adder(xs[0], ys[0], None, cmod);
@@ -503,6 +1604,93 @@ impl Builder {
         bs
     }
 
+    /// Add the mixed radix number `value` into `acc` if the mod-2 wire `include` is set, and
+    /// return `acc` unchanged otherwise, wrapping around like `addition_no_carry`. Each digit
+    /// of `value` is multiplied by `include` with a half gate before the addition.
+    pub fn masked_add(&mut self, acc: &[Ref], value: &[Ref], include: Ref) -> Vec<Ref> {
+        assert_eq!(self.modulus(include), 2, "[builder.masked_add] include must be mod 2");
+        assert_eq!(acc.len(), value.len(), "[builder.masked_add] bundles must have equal length");
+        let masked = value.iter().map(|&v| self.half_gate(v, include)).collect_vec();
+        self.addition_no_carry(acc, &masked)
+    }
+
+    /// Sum any number of base-q numbers using a tree of additions with full carry
+    /// propagation. The result has `n + ceil(log2(k))` digits where `n` is the width of the
+    /// widest of the `k` addends, which is always enough to hold the sum.
+    pub fn sum_bundles(&mut self, bundles: &[Vec<Ref>]) -> Vec<Ref> {
+        assert!(!bundles.is_empty(), "[builder.sum_bundles] no bundles given");
+        assert!(bundles.iter().all(|xs| !xs.is_empty()), "[builder.sum_bundles] empty bundle");
+        let q = self.modulus(bundles[0][0]);
+        assert!(bundles.iter().flatten().all(|&x| self.modulus(x) == q),
+            "[builder.sum_bundles] all digits must have the same modulus");
+
+        let mut layer = bundles.to_vec();
+        while layer.len() > 1 {
+            let mut next = Vec::new();
+            for pair in layer.chunks(2) {
+                if pair.len() == 2 {
+                    next.push(self.addition_with_carry_digit(&pair[0], &pair[1]));
+                } else {
+                    next.push(pair[0].clone());
+                }
+            }
+            layer = next;
+        }
+        layer.pop().unwrap()
+    }
+
+    /// Inclusive prefix sums of base-q numbers: output `i` is the sum of `bundles[0..=i]`.
+    /// Every output has `n + d` digits, where `n` is the width of the widest bundle and `d`
+    /// is the least number of digits with `q^d >= k` for `k` bundles, which is enough to
+    /// hold the total sum.
+    pub fn prefix_sum(&mut self, bundles: &[Vec<Ref>]) -> Vec<Vec<Ref>> {
+        assert!(!bundles.is_empty(), "[builder.prefix_sum] no bundles given");
+        assert!(bundles.iter().all(|xs| !xs.is_empty()), "[builder.prefix_sum] empty bundle");
+        let q = self.modulus(bundles[0][0]);
+        assert!(bundles.iter().flatten().all(|&x| self.modulus(x) == q),
+            "[builder.prefix_sum] all digits must have the same modulus");
+
+        let n = bundles.iter().map(|xs| xs.len()).max().unwrap();
+        let mut d = 0;
+        while (q as u128).pow(d as u32) < bundles.len() as u128 {
+            d += 1;
+        }
+        let zero = self.constant(0,q);
+        let pad = |xs: &[Ref]| {
+            let mut xs = xs.to_vec();
+            xs.resize(n + d, zero);
+            xs
+        };
+
+        let mut acc = pad(&bundles[0]);
+        let mut sums = vec![acc.clone()];
+        for xs in bundles[1..].iter() {
+            // the sum always fits, so the final carry would be zero
+            acc = self.addition_no_carry(&acc, &pad(xs));
+            sums.push(acc.clone());
+        }
+        sums
+    }
+
+    // adds two base-q numbers, padding the shorter one with zeros, and appends the final
+    // carry as an extra base-q digit
+    fn addition_with_carry_digit(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        let q = self.modulus(xs[0]);
+        let n = xs.len().max(ys.len());
+        let zero = self.constant(0,q);
+        let mut c = None;
+        let mut zs = Vec::with_capacity(n+1);
+        for i in 0..n {
+            let x = *xs.get(i).unwrap_or(&zero);
+            let y = *ys.get(i).unwrap_or(&zero);
+            let (z, carry) = self.adder(x, y, c, q);
+            zs.push(z);
+            c = Some(carry);
+        }
+        zs.push(c.unwrap());
+        zs
+    }
+
     fn adder(&mut self, x: Ref, y: Ref, opt_c: Option<Ref>, carry_modulus: u16) -> (Ref, Ref) {
         let q = self.modulus(x);
         assert_eq!(q, self.modulus(y));
@@ -552,6 +1740,468 @@ impl Builder {
         }
     }
 
+    // the subtractive analogue of addition_no_carry: computes (X - Y) mod q^n in base q,
+    // propagating borrows but not creating gates for the final one
+    pub fn subtraction_no_borrow(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        assert_eq!(xs.len(), ys.len());
+
+        let mut borrow = None;
+        let mut bs = Vec::with_capacity(xs.len());
+        for i in 0..xs.len()-1 {
+            let bmod = self.modulus(xs[i+1]);
+            let (z, b) = self.subtractor(xs[i], ys[i], borrow, bmod);
+            borrow = Some(b);
+            bs.push(z);
+        }
+        let n = xs.len() - 1;
+        let mut z = self.sub(xs[n], ys[n]);
+        if let Some(b) = borrow {
+            z = self.sub(z, b);
+        }
+        bs.push(z);
+        bs
+    }
+
+    fn subtractor(&mut self, x: Ref, y: Ref, opt_b: Option<Ref>, borrow_modulus: u16) -> (Ref, Ref) {
+        let q = self.modulus(x);
+        assert_eq!(q, self.modulus(y));
+
+        let mut diff = self.sub(x,y);
+        if let Some(b) = opt_b {
+            diff = self.sub(diff,b);
+        }
+
+        // x - y - b lies in [-q, q-1], so mod qp the negative values are exactly those >= q
+        let qp = if opt_b.is_some() { 2*q } else { 2*q-1 };
+        let xp = self.mod_change(x, qp);
+        let yp = self.mod_change(y, qp);
+        let mut zp = self.sub(xp, yp);
+        if let Some(b) = opt_b {
+            let bp = self.mod_change(b, qp);
+            zp = self.sub(zp, bp);
+        }
+
+        let tt = (0..qp).map(|x| u16::from(x >= q)).collect();
+        let borrow = self.proj(zp, borrow_modulus, tt);
+        (diff, borrow)
+    }
+
+    /// Subtract the mixed radix number `ys` from `xs`, returning the difference modulo the
+    /// capacity and the final borrow as a mod-2 wire, which is 1 exactly when `xs < ys`.
+    /// Both must have the same moduli.
+    pub fn subtraction(&mut self, xs: &[Ref], ys: &[Ref]) -> (Vec<Ref>, Ref) {
+        assert_eq!(xs.len(), ys.len());
+        let mut borrow = None;
+        let mut zs = Vec::with_capacity(xs.len());
+        for i in 0..xs.len() {
+            let bmod = xs.get(i+1).map_or(2, |&x| self.modulus(x));
+            let (z, b) = self.subtractor(xs[i], ys[i], borrow, bmod);
+            borrow = Some(b);
+            zs.push(z);
+        }
+        (zs, borrow.unwrap())
+    }
+
+    /// Whether the mixed radix number `xs` is less than `ys`, as a mod-2 wire. Both must
+    /// have the same moduli.
+    pub fn less_than(&mut self, xs: &[Ref], ys: &[Ref]) -> Ref {
+        self.subtraction(xs, ys).1
+    }
+
+    /// Reduce the mixed radix number `xs` modulo `p`, as a single wire of modulus `p`. This
+    /// is exact for every value `xs` can hold, so there is no bound on the input beyond the
+    /// capacity of `xs`.
+    ///
+    /// No Barrett-style quotient estimate is needed: reduction mod `p` respects sums and
+    /// products, so each digit is projected to its contribution `d * w mod p`, where `w` is
+    /// the place value of the digit, and the contributions are added for free. This costs
+    /// one projection per digit, or nothing for digits which already have modulus `p`.
+    pub fn barrett_reduce(&mut self, xs: &[Ref], p: u16) -> Ref {
+        Self::check_modulus("barrett_reduce", p);
+        assert!(!xs.is_empty(), "[builder.barrett_reduce] empty bundle");
+        let mut w = 1 % p as u32;
+        let terms = xs.iter().map(|&x| {
+            let q = self.modulus(x);
+            let z = if q == p {
+                self.cmul(x, w as u16)
+            } else {
+                let tt = (0..q as u32).map(|d| (d * w % p as u32) as u16).collect();
+                self.proj(x, p, tt)
+            };
+            w = w * q as u32 % p as u32;
+            z
+        }).collect_vec();
+        if terms.len() == 1 {
+            terms[0]
+        } else {
+            self.add_many(&terms)
+        }
+    }
+
+    /// Compare the mixed radix numbers `xs` and `ys`, which must have the same moduli, as a
+    /// mod-3 wire: 0 if `xs < ys`, 1 if they are equal and 2 if `xs > ys`.
+    ///
+    /// Each digit pair is classified with a single projection of their difference, as in
+    /// `subtractor`, and the running result is updated with a 3x3 yao gate which keeps it
+    /// unless the digits differ, costing about 8 ciphertexts a digit more than `less_than`.
+    pub fn compare3(&mut self, xs: &[Ref], ys: &[Ref]) -> Ref {
+        assert_eq!(xs.len(), ys.len(), "[builder.compare3] bundles must have equal length");
+        assert!(!xs.is_empty(), "[builder.compare3] empty bundles");
+        let mut state = None;
+        for (&x, &y) in xs.iter().zip(ys.iter()) {
+            let q = self.modulus(x);
+            assert_eq!(q, self.modulus(y), "[builder.compare3] modulus mismatch");
+            // x - y mod 2q-1 is 0 when equal, below q when greater, and at least q when less
+            let qp = 2*q - 1;
+            let xp = self.mod_change(x, qp);
+            let yp = self.mod_change(y, qp);
+            let d = self.sub(xp, yp);
+            let tt = (0..qp).map(|v| if v == 0 { 1 } else if v < q { 2 } else { 0 }).collect();
+            let class = self.proj(d, 3, tt);
+            state = Some(match state {
+                None => class,
+                Some(s) => {
+                    let tt = (0..3).map(|c| (0..3).map(|s| if c == 1 { s } else { c }).collect()).collect();
+                    self.yao(class, s, 3, tt)
+                }
+            });
+        }
+        state.unwrap()
+    }
+
+    /// Whether the bundles `xs` and `ys` are equal, as a mod-2 wire. They must have the same
+    /// moduli. Each digit difference is projected to whether it is nonzero, these are
+    /// counted for free, and one more projection tests the count for zero.
+    pub fn eq(&mut self, xs: &[Ref], ys: &[Ref]) -> Ref {
+        assert_eq!(xs.len(), ys.len(), "[builder.eq] bundles must have equal length");
+        assert!(!xs.is_empty(), "[builder.eq] empty bundles");
+        let n = xs.len();
+        if n == 1 {
+            let q = self.modulus(xs[0]);
+            let d = self.sub(xs[0], ys[0]);
+            return self.proj(d, 2, (0..q).map(|v| (v == 0) as u16).collect());
+        }
+        let nonzero = xs.iter().zip(ys.iter()).map(|(&x,&y)| {
+            let q = self.modulus(x);
+            let d = self.sub(x,y);
+            self.proj(d, n as u16 + 1, (0..q).map(|v| (v != 0) as u16).collect())
+        }).collect_vec();
+        let count = self.add_many(&nonzero);
+        let mut tt = vec![0; n+1];
+        tt[0] = 1;
+        self.proj(count, 2, tt)
+    }
+
+    /// Whether the mixed radix number `x` is one of the public values in `set`, as a mod-2
+    /// wire. This is an `eq` against each distinct value which `x` can hold, so its cost is
+    /// linear in the size of the set; since at most one of them can match, their OR is
+    /// their free XOR.
+    pub fn is_member(&mut self, x: &[Ref], set: &[u128]) -> Ref {
+        let ms = x.iter().map(|&d| self.modulus(d)).collect_vec();
+        let cap = ms.iter().try_fold(1u128, |acc, &m| acc.checked_mul(m as u128));
+        let vals = set.iter().cloned()
+            .filter(|&v| cap.is_none_or(|cap| v < cap))
+            .unique()
+            .collect_vec();
+        let mut z = self.constant(0,2);
+        for v in vals {
+            let c = self.constant_mixed_radix(v, &ms);
+            let e = self.eq(x, &c);
+            z = self.xor(z, e);
+        }
+        z
+    }
+
+    /// Whether the mixed radix number `x` is less than each of the public `thresholds`, as
+    /// one mod-2 wire per threshold.
+    ///
+    /// This shares work between the comparisons: each digit of `x` is moved to twice its
+    /// modulus just once, the constant digits of the thresholds are subtracted for free,
+    /// and borrows are carried in twice the next digit's modulus so they need no modulus
+    /// change. Each threshold then costs a single projection per digit, and thresholds with
+    /// the same low digits share their borrow chains up to the first digit that differs.
+    pub fn compare_many(&mut self, x: &[Ref], thresholds: &[u128]) -> Vec<Ref> {
+        assert!(!x.is_empty(), "[builder.compare_many] empty bundle");
+        let ms = x.iter().map(|&d| self.modulus(d)).collect_vec();
+        let cap = ms.iter().try_fold(1u128, |acc, &m| acc.checked_mul(m as u128));
+        let n = ms.len();
+        let borrow_mod = |i: usize| if i+1 < n { 2*ms[i+1] } else { 2 };
+
+        let mut xps: Vec<Option<Ref>> = vec![None; n];
+        let mut borrows: HashMap<(usize, u16, Option<Ref>), Ref> = HashMap::new();
+        thresholds.iter().map(|&t| {
+            if cap.is_some_and(|cap| t >= cap) {
+                return self.constant(1,2);
+            }
+            let cs = numbers::as_mixed_radix(t, &ms);
+            let mut b = None;
+            for i in 0..n {
+                let key = (i, cs[i], b);
+                if let Some(&z) = borrows.get(&key) {
+                    b = Some(z);
+                    continue;
+                }
+                let (q, c) = (ms[i], cs[i]);
+                let z = match b {
+                    // the first digit borrows exactly when it is below the threshold's
+                    None => self.proj(x[i], borrow_mod(i), (0..q).map(|v| (v < c) as u16).collect()),
+                    Some(b) => {
+                        let xp = *xps[i].get_or_insert_with(|| self.mod_change(x[i], 2*q));
+                        let cp = self.constant(c, 2*q);
+                        let zp = self.sub(xp, cp);
+                        let zp = self.sub(zp, b);
+                        // x - c - b lies in [-q, q-1], so mod 2q the negative values are those >= q
+                        self.proj(zp, borrow_mod(i), (0..2*q).map(|v| (v >= q) as u16).collect())
+                    }
+                };
+                borrows.insert(key, z);
+                b = Some(z);
+            }
+            b.unwrap()
+        }).collect()
+    }
+
+    /// Place the mixed radix number `x` in one of the buckets `[boundaries[i],
+    /// boundaries[i+1])`, returning one mod-2 wire per bucket, set exactly when `x` lies in
+    /// it. The boundaries must be strictly increasing; values below the first or at least
+    /// the last lie in no bucket. Costs one `compare_many`: since the comparisons are
+    /// monotone, each bucket is the free XOR of the comparisons with its two ends.
+    pub fn bucketize(&mut self, x: &[Ref], boundaries: &[u128]) -> Vec<Ref> {
+        assert!(boundaries.len() >= 2, "[builder.bucketize] need at least two boundaries");
+        assert!(boundaries.windows(2).all(|w| w[0] < w[1]), "[builder.bucketize] boundaries must be increasing");
+        let lts = self.compare_many(x, boundaries);
+        lts.windows(2).map(|w| self.xor(w[0], w[1])).collect()
+    }
+
+    /// Whether the mixed radix number `xs` lies in the interval `[lo, hi]`, as a mod-2 wire.
+    pub fn in_range(&mut self, xs: &[Ref], lo: u128, hi: u128) -> Ref {
+        assert!(lo <= hi, "[builder.in_range] empty range [{}, {}]", lo, hi);
+        let ms = xs.iter().map(|&x| self.modulus(x)).collect_vec();
+        // None when the capacity of xs does not fit in a u128
+        let cap = ms.iter().try_fold(1u128, |acc, &m| acc.checked_mul(m as u128));
+        assert!(cap.is_none_or(|cap| lo < cap), "[builder.in_range] lower bound {} is too large", lo);
+
+        let mut bits = Vec::new();
+        if lo > 0 {
+            let lo_bundle = self.constant_mixed_radix(lo, &ms);
+            let lt = self.less_than(xs, &lo_bundle);
+            bits.push(self.negate(lt));
+        }
+        match hi.checked_add(1) {
+            Some(h) if cap.is_none_or(|cap| h < cap) => {
+                let hi_bundle = self.constant_mixed_radix(h, &ms);
+                bits.push(self.less_than(xs, &hi_bundle));
+            }
+            _ => (),
+        }
+        match bits.len() {
+            0 => self.constant(1,2),
+            1 => bits[0],
+            _ => self.and(bits[0], bits[1]),
+        }
+    }
+
+    /// Returns `then_v` if the mixed radix number `a` is less than `b`, and `else_v`
+    /// otherwise. `a` and `b` must have the same moduli, as must `then_v` and `else_v`,
+    /// but the two pairs need not match each other.
+    pub fn select_by_compare(&mut self, a: &[Ref], b: &[Ref], then_v: &[Ref], else_v: &[Ref]) -> Vec<Ref> {
+        assert_eq!(then_v.len(), else_v.len(), "[builder.select_by_compare] branches must have equal length");
+        let lt = self.less_than(a, b);
+        else_v.iter().zip(then_v.iter()).map(|(&x,&y)| self.mux(lt, x, y)).collect()
+    }
+
+    /// The larger of the mixed radix numbers `xs` and `ys`, which must have the same moduli.
+    pub fn max(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        let lt = self.less_than(xs, ys);
+        xs.iter().zip(ys.iter()).map(|(&x,&y)| self.mux(lt, x, y)).collect()
+    }
+
+    /// The smaller of the mixed radix numbers `xs` and `ys`, which must have the same moduli.
+    pub fn min(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        let lt = self.less_than(xs, ys);
+        xs.iter().zip(ys.iter()).map(|(&x,&y)| self.mux(lt, y, x)).collect()
+    }
+
+    /// The smaller and the larger of the mixed radix numbers `xs` and `ys`, in that order.
+    /// This is the comparator of a sorting network: one `less_than` driving a `cond_swap`.
+    pub fn sort2(&mut self, xs: &[Ref], ys: &[Ref]) -> (Vec<Ref>, Vec<Ref>) {
+        let swap = self.less_than(ys, xs);
+        self.cond_swap(swap, xs, ys)
+    }
+
+    /// Fold each of the mixed radix numbers `items` into the accumulator `init` in turn
+    /// with `op`, using `addition_no_carry`, `max` or `min`. All must have the same moduli.
+    pub fn fold(&mut self, init: &[Ref], items: &[Vec<Ref>], op: FoldOp) -> Vec<Ref> {
+        items.iter().fold(init.to_vec(), |acc, xs| {
+            match op {
+                FoldOp::Add => self.addition_no_carry(&acc, xs),
+                FoldOp::Max => self.max(&acc, xs),
+                FoldOp::Min => self.min(&acc, xs),
+            }
+        })
+    }
+
+    /// Clamp the mixed radix number `xs` to the interval `[lo, hi]`: `lo` if it is below
+    /// `lo`, `hi` if it is above `hi`, and itself otherwise.
+    pub fn clamp(&mut self, xs: &[Ref], lo: u128, hi: u128) -> Vec<Ref> {
+        assert!(lo <= hi, "[builder.clamp] empty range [{}, {}]", lo, hi);
+        let ms = xs.iter().map(|&x| self.modulus(x)).collect_vec();
+        let cap = ms.iter().try_fold(1u128, |acc, &m| acc.checked_mul(m as u128));
+        assert!(cap.is_none_or(|cap| hi < cap), "[builder.clamp] upper bound {} is too large", hi);
+
+        let mut zs = xs.to_vec();
+        if lo > 0 {
+            let lo_bundle = self.constant_mixed_radix(lo, &ms);
+            let below = self.less_than(xs, &lo_bundle);
+            zs = zs.iter().zip(lo_bundle.iter()).map(|(&z,&l)| self.mux(below, z, l)).collect();
+        }
+        if cap.is_none_or(|cap| hi < cap - 1) {
+            let hi_bundle = self.constant_mixed_radix(hi, &ms);
+            let above = self.less_than(&hi_bundle, xs);
+            zs = zs.iter().zip(hi_bundle.iter()).map(|(&z,&h)| self.mux(above, z, h)).collect();
+        }
+        zs
+    }
+
+    /// Obliviously sort the mixed radix numbers in `bundles` into ascending order, using
+    /// Batcher's odd-even merge sorting network. The network is public and only the
+    /// compare-exchanges depend on the data; it uses O(n log^2 n) of them, each a
+    /// `less_than` followed by a `cond_swap`. All bundles must have the same moduli.
+    pub fn sort(&mut self, bundles: &[Vec<Ref>]) -> Vec<Vec<Ref>> {
+        let mut xs = bundles.to_vec();
+        for (a, b) in batcher_network(xs.len()) {
+            self.compare_exchange(&mut xs, a, b);
+        }
+        xs
+    }
+
+    /// The median of the mixed radix numbers in `bundles`, taking the lower of the two
+    /// middle values when there is an even number of them. This runs the network of `sort`,
+    /// leaving out every compare-exchange which cannot affect the middle position.
+    pub fn median(&mut self, bundles: &[Vec<Ref>]) -> Vec<Ref> {
+        assert!(!bundles.is_empty(), "[builder.median] no bundles given");
+        let n = bundles.len();
+        let network = batcher_network(n);
+
+        // walk backwards from the middle, keeping the compare-exchanges it depends on
+        let mut needed = vec![false; n];
+        needed[(n-1) / 2] = true;
+        let mut keep = vec![false; network.len()];
+        for (i, &(a, b)) in network.iter().enumerate().rev() {
+            if needed[a] || needed[b] {
+                keep[i] = true;
+                needed[a] = true;
+                needed[b] = true;
+            }
+        }
+
+        let mut xs = bundles.to_vec();
+        for (&(a, b), _) in network.iter().zip(keep.iter()).filter(|(_, &k)| k) {
+            self.compare_exchange(&mut xs, a, b);
+        }
+        xs.swap_remove((n-1) / 2)
+    }
+
+    // put the smaller of xs[a] and xs[b] into xs[a] and the larger into xs[b]
+    fn compare_exchange(&mut self, xs: &mut [Vec<Ref>], a: usize, b: usize) {
+        let (lo, hi) = self.sort2(&xs[a], &xs[b]);
+        xs[a] = lo;
+        xs[b] = hi;
+    }
+
+    /// The index of the largest of the mixed radix numbers in `bundles`, as a wire with
+    /// modulus `bundles.len()`. Computed by a tournament tree of `less_than` comparisons,
+    /// muxing both the running maximum and its index. Ties resolve to the lowest index.
+    pub fn argmax(&mut self, bundles: &[Vec<Ref>]) -> Ref {
+        let n = bundles.len();
+        assert!(n >= 2, "[builder.argmax] need at least two bundles");
+        assert!(n <= u16::MAX as usize, "[builder.argmax] too many bundles");
+        let mut round = bundles.iter().enumerate().map(|(i, xs)| {
+            (xs.clone(), self.constant(i as u16, n as u16))
+        }).collect_vec();
+
+        while round.len() > 1 {
+            let mut next = Vec::with_capacity(round.len() / 2 + 1);
+            for pair in round.chunks(2) {
+                if pair.len() == 1 {
+                    next.push(pair[0].clone());
+                    continue;
+                }
+                let ((xs, i), (ys, j)) = (&pair[0], &pair[1]);
+                // xs holds the lower indices, so it wins unless strictly smaller
+                let sel = self.less_than(xs, ys);
+                let zs = xs.iter().zip(ys.iter()).map(|(&x,&y)| self.mux(sel, x, y)).collect();
+                let k = self.mux(sel, *i, *j);
+                next.push((zs, k));
+            }
+            round = next;
+        }
+        round[0].1
+    }
+
+    /// The index of the smallest of the mixed radix numbers in `bundles`, as a wire with
+    /// modulus `bundles.len()`, together with that smallest bundle. Like `argmax`, the
+    /// tournament tree already carries the running minimum, so the value comes for free.
+    /// Ties resolve to the lowest index.
+    pub fn argmin_with_value(&mut self, bundles: &[Vec<Ref>]) -> (Ref, Vec<Ref>) {
+        let n = bundles.len();
+        assert!(n >= 2, "[builder.argmin_with_value] need at least two bundles");
+        assert!(n <= u16::MAX as usize, "[builder.argmin_with_value] too many bundles");
+        let mut round = bundles.iter().enumerate().map(|(i, xs)| {
+            (xs.clone(), self.constant(i as u16, n as u16))
+        }).collect_vec();
+
+        while round.len() > 1 {
+            let mut next = Vec::with_capacity(round.len() / 2 + 1);
+            for pair in round.chunks(2) {
+                if pair.len() == 1 {
+                    next.push(pair[0].clone());
+                    continue;
+                }
+                let ((xs, i), (ys, j)) = (&pair[0], &pair[1]);
+                // xs holds the lower indices, so it wins unless ys is strictly smaller
+                let sel = self.less_than(ys, xs);
+                let zs = xs.iter().zip(ys.iter()).map(|(&x,&y)| self.mux(sel, x, y)).collect();
+                let k = self.mux(sel, *i, *j);
+                next.push((zs, k));
+            }
+            round = next;
+        }
+        let (zs, k) = round.pop().unwrap();
+        (k, zs)
+    }
+
+    fn constant_mixed_radix(&mut self, x: u128, ms: &[u16]) -> Vec<Ref> {
+        let ds = numbers::as_mixed_radix(x, ms);
+        ds.into_iter().zip(ms.iter()).map(|(d,&m)| self.constant(d,m)).collect()
+    }
+
+    // Convert the mixed radix number xs to binary, by comparing it to and conditionally
+    // subtracting each power of two below its capacity, from the highest down. Free when
+    // xs is already binary.
+    fn mixed_radix_to_binary(&mut self, xs: &[Ref]) -> Vec<Ref> {
+        let ms = xs.iter().map(|&x| self.modulus(x)).collect_vec();
+        if ms.iter().all(|&m| m == 2) {
+            return xs.to_vec();
+        }
+        let cap = ms.iter().try_fold(1u128, |acc, &m| acc.checked_mul(m as u128))
+            .expect("[builder.mixed_radix_to_binary] capacity does not fit in a u128");
+        let nbits = 128 - (cap - 1).leading_zeros() as usize;
+
+        let mut rest = xs.to_vec();
+        let mut bits = Vec::with_capacity(nbits);
+        for k in (0..nbits).rev() {
+            let pow = self.constant_mixed_radix(1 << k, &ms);
+            let (diff, borrow) = self.subtraction(&rest, &pow);
+            if k > 0 {
+                rest = diff.iter().zip(rest.iter()).map(|(&d,&r)| self.mux(borrow, d, r)).collect();
+            }
+            bits.push(self.negate(borrow));
+        }
+        bits.reverse();
+        bits
+    }
+
     pub fn twos_complement(&mut self, xs: &[Ref]) -> Vec<Ref> {
         let not_xs = xs.iter().map(|&x| self.negate(x)).collect_vec();
         let zero = self.constant(0,2);
@@ -571,6 +2221,215 @@ impl Builder {
         (zs, self.negate(c))
     }
 
+    /// Schoolbook multiplication of the binary numbers `xs` and `ys`. The result has
+    /// `xs.len() + ys.len()` bits, so it never overflows.
+    pub fn binary_multiplication(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        let n = xs.len();
+        let zero = self.constant(0,2);
+        let mut acc = vec![zero; n + ys.len()];
+        for (i, &y) in ys.iter().enumerate() {
+            let pp = xs.iter().map(|&x| self.and(x,y)).collect_vec();
+            if i == 0 {
+                acc[..n].copy_from_slice(&pp);
+            } else {
+                let (zs, c) = self.addition(&acc[i..i+n], &pp);
+                acc[i..i+n].copy_from_slice(&zs);
+                acc[i+n] = c;
+            }
+        }
+        acc
+    }
+
+    /// Multiply the two's complement binary numbers `xs` and `ys`, returning the full
+    /// `xs.len() + ys.len()` bit two's complement product, which never overflows. Both are
+    /// sign extended to that width (free) before an unsigned `binary_multiplication` whose
+    /// high half is discarded, so this costs about four times as much as multiplying
+    /// unsigned numbers of the original widths.
+    pub fn signed_mul(&mut self, xs: &[Ref], ys: &[Ref]) -> Vec<Ref> {
+        let w = xs.len() + ys.len();
+        let mut xs_ext = xs.to_vec();
+        let sx = self.sign(xs);
+        xs_ext.resize(w, sx);
+        let mut ys_ext = ys.to_vec();
+        let sy = self.sign(ys);
+        ys_ext.resize(w, sy);
+        let zs = self.binary_multiplication(&xs_ext, &ys_ext);
+        zs[..w].to_vec()
+    }
+
+    /// Logical right shift of the binary number `xs` by `k` bits, keeping its width. Free.
+    pub fn shift_right(&mut self, xs: &[Ref], k: usize) -> Vec<Ref> {
+        let zero = self.constant(0,2);
+        let k = k.min(xs.len());
+        let mut zs = xs[k..].to_vec();
+        zs.resize(xs.len(), zero);
+        zs
+    }
+
+    /// Multiply the unsigned fixed-point binary numbers `xs` and `ys`, each with
+    /// `scale_bits` fractional bits, returning a number of the same width and scale. The
+    /// low `scale_bits` bits of the full product are truncated, so the result is rounded
+    /// down and is at most one unit in the last place below the exact product. High bits
+    /// which do not fit in `xs.len()` bits are discarded.
+    pub fn fixed_mul(&mut self, xs: &[Ref], ys: &[Ref], scale_bits: usize) -> Vec<Ref> {
+        assert_eq!(xs.len(), ys.len(), "[builder.fixed_mul] bundles must have equal length");
+        assert!(scale_bits <= xs.len(), "[builder.fixed_mul] scale exceeds width");
+        let zs = self.binary_multiplication(xs, ys);
+        let zs = self.shift_right(&zs, scale_bits);
+        zs[..xs.len()].to_vec()
+    }
+
+    /// Multiply two `Float`s with the same exponent and mantissa widths. The signs are
+    /// XORed, the exponents added and the mantissas multiplied with `binary_multiplication`.
+    /// Since both mantissas are normalized, the product has at most one leading zero, so
+    /// renormalizing is a single conditional shift by one bit, which is also carried into
+    /// the exponent sum. The low bits of the product are truncated, so the result is
+    /// rounded toward zero with a relative error below `2^(1 - mantissa.len())`. The
+    /// exponent wraps around if it overflows.
+    pub fn float_mul(&mut self, x: &Float, y: &Float) -> Float {
+        let m = x.mantissa.len();
+        assert!(m > 0, "[builder.float_mul] empty mantissa");
+        assert_eq!(m, y.mantissa.len(), "[builder.float_mul] mantissas must have equal length");
+        assert!(!x.exponent.is_empty(), "[builder.float_mul] empty exponent");
+        assert_eq!(x.exponent.len(), y.exponent.len(), "[builder.float_mul] exponents must have equal length");
+
+        let sign = self.xor(x.sign, y.sign);
+        let ps = self.binary_multiplication(&x.mantissa, &y.mantissa);
+        let top = ps[2*m-1];
+        let mantissa = ps[m-1..2*m-1].iter().zip(ps[m..].iter())
+            .map(|(&lo, &hi)| self.mux(top, lo, hi))
+            .collect();
+
+        // add the exponents with the renormalizing shift as the carry in
+        let e = x.exponent.len();
+        let mut c = top;
+        let mut exponent = Vec::with_capacity(e);
+        for i in 0..e-1 {
+            let (z, carry) = self.adder(x.exponent[i], y.exponent[i], Some(c), 2);
+            exponent.push(z);
+            c = carry;
+        }
+        exponent.push(self.add_many(&[x.exponent[e-1], y.exponent[e-1], c]));
+
+        Float { sign, exponent, mantissa }
+    }
+
+    /// The floor of the square root of the mixed radix number `xs`, as a binary number of
+    /// `ceil(n/2)` bits, where `n` is the number of bits needed to hold any value of `xs`.
+    ///
+    /// Inputs which are not binary are first converted, at the cost of `n` mixed radix
+    /// subtractions with a `mux` of every digit. The root is then found a bit at a time by
+    /// the restoring algorithm: each of the `ceil(n/2)` steps is a binary subtraction of
+    /// about `n/2` bits and a `mux` of each bit of the remainder.
+    pub fn isqrt(&mut self, xs: &[Ref]) -> Vec<Ref> {
+        let bits = self.mixed_radix_to_binary(xs);
+        let h = bits.len().div_ceil(2);
+        let zero = self.constant(0,2);
+        let one = self.constant(1,2);
+
+        // rem <= 2*root, so after shifting in two more bits it fits in h+2 bits
+        let mut rem = vec![zero; h+2];
+        let mut root = vec![zero; h];
+        for i in (0..h).rev() {
+            let mut shifted = vec![bits[2*i], *bits.get(2*i+1).unwrap_or(&zero)];
+            shifted.extend_from_slice(&rem[..h]);
+            let mut trial = vec![one, zero];
+            trial.extend_from_slice(&root);
+            let (diff, borrow) = self.subtraction(&shifted, &trial);
+            if i > 0 {
+                rem = diff.iter().zip(shifted.iter()).map(|(&d,&s)| self.mux(borrow, d, s)).collect();
+            }
+            root.pop();
+            root.insert(0, self.negate(borrow));
+        }
+        root
+    }
+
+    /// Divide the unsigned binary number `num` by the unsigned binary number `den`, returning
+    /// the quotient, with as many bits as `num`, and the remainder, with as many bits as
+    /// `den`. This is restoring long division: for each bit of `num` the partial remainder is
+    /// shifted, `den` is subtracted, and the result kept only if there was no borrow, so it
+    /// costs one subtraction of `den.len() + 1` bits and a `mux` of each of its bits per bit
+    /// of `num`. Dividing by zero gives a quotient of all ones and a remainder of the low
+    /// `den.len()` bits of `num`.
+    pub fn div_mod(&mut self, num: &[Ref], den: &[Ref]) -> (Vec<Ref>, Vec<Ref>) {
+        assert!(!num.is_empty() && !den.is_empty(), "[builder.div_mod] empty bundle");
+        assert!(num.iter().chain(den.iter()).all(|&x| self.modulus(x) == 2),
+            "[builder.div_mod] expected binary inputs");
+        let m = den.len();
+        let zero = self.constant(0,2);
+        let mut den_ext = den.to_vec();
+        den_ext.push(zero);
+
+        // rem < den, so after shifting in one more bit it fits in m+1 bits
+        let mut rem = vec![zero; m];
+        let mut quot = vec![zero; num.len()];
+        for i in (0..num.len()).rev() {
+            let mut shifted = vec![num[i]];
+            shifted.extend_from_slice(&rem);
+            let (diff, borrow) = self.subtraction(&shifted, &den_ext);
+            rem = diff[..m].iter().zip(shifted.iter()).map(|(&d,&s)| self.mux(borrow, d, s)).collect();
+            quot[i] = self.negate(borrow);
+        }
+        (quot, rem)
+    }
+
+    /// The sign bit of the two's complement binary number `xs`: 1 if it is negative.
+    pub fn sign(&mut self, xs: &[Ref]) -> Ref {
+        assert!(xs.iter().all(|&x| self.modulus(x) == 2), "[builder.sign] expected binary input");
+        *xs.last().unwrap()
+    }
+
+    /// The sign of the two's complement binary number `xs` as a mod-3 wire: 2 (that is, -1)
+    /// if it is negative, 0 if it is zero, and 1 if it is positive.
+    pub fn signum(&mut self, xs: &[Ref]) -> Ref {
+        let s = self.sign(xs);
+        let nonzero = self.or_many(xs);
+        // negative numbers are nonzero, so this sums to 2 exactly when s is set
+        let s = self.proj(s, 3, vec![0,1]);
+        let nonzero = self.proj(nonzero, 3, vec![0,1]);
+        self.add(s, nonzero)
+    }
+
+    /// The number of zero bits above the highest set bit of the binary number `xs`, which
+    /// is `xs.len()` when it is zero, as a binary number of `ceil(log2(xs.len()+1))` bits.
+    ///
+    /// Computed by a priority encoder: `xs` is padded at the bottom with ones to a power of
+    /// two width, and halves are combined by a tree of `mux`es selecting the count of the
+    /// low half whenever the high half is zero. About `n log2(n)` half gates in total.
+    pub fn count_leading_zeros(&mut self, xs: &[Ref]) -> Vec<Ref> {
+        assert!(!xs.is_empty(), "[builder.count_leading_zeros] empty bundle");
+        assert!(xs.iter().all(|&x| self.modulus(x) == 2), "[builder.count_leading_zeros] expected binary input");
+        let n = xs.len();
+        let one = self.constant(1,2);
+        let mut padded = vec![one; n.next_power_of_two() - n];
+        padded.extend_from_slice(xs);
+        let mut zs = self.clz_rec(&padded);
+        // the count is at most n, so the remaining high bits are always zero
+        zs.truncate(64 - (n as u64).leading_zeros() as usize);
+        zs
+    }
+
+    // clz of a power of two width binary number, with one more bit than the width needs;
+    // that top bit is set exactly when the number is zero
+    fn clz_rec(&mut self, xs: &[Ref]) -> Vec<Ref> {
+        if xs.len() == 1 {
+            let one = self.constant(1,2);
+            return vec![self.xor(xs[0], one)];
+        }
+        let m = xs.len() / 2;
+        let lo = self.clz_rec(&xs[..m]);
+        let hi = self.clz_rec(&xs[m..]);
+        let k = lo.len() - 1;
+        let (zl, zh) = (lo[k], hi[k]);
+        // when the high half is zero the count is m + lo, otherwise it is hi < m
+        let mut zs = (0..k).map(|i| self.mux(zh, hi[i], lo[i])).collect_vec();
+        let both = self.and(zh, zl);
+        zs.push(self.xor(zh, both));
+        zs.push(both);
+        zs
+    }
+
 }
 
 
@@ -789,6 +2648,59 @@ mod tests {
             assert_eq!(numbers::from_base_q(&res, q), z % Q);
         }
     }
+//}}}
+    #[test] // single_digit_addition_no_carry {{{
+    fn single_digit_addition_no_carry() {
+        let mut b = Builder::new();
+        let mut rng = rand::thread_rng();
+
+        let q = rng.gen_modulus();
+        let x = b.input(q);
+        let y = b.input(q);
+        let zs = b.addition_no_carry(&[x], &[y]);
+        assert_eq!(zs.len(), 1);
+        b.outputs(&zs);
+        let c = b.finish();
+
+        for _ in 0..16 {
+            let x = rng.gen_u16() % q;
+            let y = rng.gen_u16() % q;
+            assert_eq!(c.eval(&[x,y]), vec![(x + y) % q]);
+        }
+    }
+//}}}
+    #[test] // base_q_subtraction_no_borrow {{{
+    fn base_q_subtraction_no_borrow() {
+        let mut b = Builder::new();
+        let mut rng = rand::thread_rng();
+
+        let q = rng.gen_modulus();
+        let n = 16;
+        let xs = b.inputs(n,q);
+        let ys = b.inputs(n,q);
+        let zs = b.subtraction_no_borrow(&xs, &ys);
+        b.outputs(&zs);
+        let c = b.finish();
+
+        // test maximum underflow
+        let Q = (q as u128).pow(n as u32);
+        let x = 0;
+        let y = Q - 1;
+        let mut ds = numbers::as_base_q(x,q,n);
+        ds.extend(numbers::as_base_q(y,q,n).iter());
+        let res = c.eval(&ds);
+        assert_eq!(numbers::from_base_q(&res, q), 1);
+
+        // test random values
+        for _ in 0..64 {
+            let x = rng.gen_u128() % Q;
+            let y = rng.gen_u128() % Q;
+            let mut ds = numbers::as_base_q(x,q,n);
+            ds.extend(numbers::as_base_q(y,q,n).iter());
+            let res = c.eval(&ds);
+            assert_eq!(numbers::from_base_q(&res, q), (x + Q - y) % Q);
+        }
+    }
 //}}}
     #[test] // fancy_addition {{{
     fn fancy_addition() {
@@ -871,5 +2783,482 @@ mod tests {
         assert_eq!(circ, Circuit::from_str(&circ.to_string()).unwrap());
     }
 //}}}
+    #[test] // free_gate_ratio {{{
+    fn free_gate_ratio() {
+        let mut b = Builder::new();
+        assert_eq!(b.borrow_circ().free_gate_ratio(), 1.0);
+
+        let x = b.input(3);
+        let y = b.input(3);
+        let z = b.add(x,y);
+        let w = b.cmul(z,2);
+        assert_eq!(b.borrow_circ().free_gate_ratio(), 1.0);
+
+        let p = b.proj(w, 2, vec![0,1,1]);
+        let q = b.input(2);
+        let r = b.half_gate(p,q);
+        b.output(r);
+        let c = b.finish();
+
+        // 5 free gates out of 7
+        assert_eq!(c.free_gate_ratio(), 5.0 / 7.0);
+    }
+//}}}
+    #[test] // input_output_moduli {{{
+    fn input_output_moduli() {
+        let mut b = Builder::new();
+        let x = b.input(3);
+        let y = b.input(2);
+        let z = b.input(7);
+        let w = b.half_gate(x,y);
+        let v = b.mod_change(z,5);
+        b.output(v);
+        b.output(w);
+        b.output(y);
+        let c = b.finish();
+
+        assert_eq!(c.input_moduli(), vec![3,2,7]);
+        assert_eq!(c.output_moduli(), vec![5,3,2]);
+        assert_eq!(c.input_moduli(), (0..c.ninputs()).map(|i| c.input_mod(i)).collect_vec());
+        assert_eq!(c.output_moduli(), c.output_refs.iter().map(|&r| c.modulus(r)).collect_vec());
+    }
+//}}}
+    #[test] // stats_json {{{
+    fn stats_json() {
+        let mut b = Builder::new();
+        let x = b.input(3);
+        let y = b.input(3);
+        let z = b.add(x,y);
+        let w = b.cmul(z,2);
+        let p = b.proj(w, 2, vec![0,1,1]);
+        let q = b.input(2);
+        let r = b.half_gate(p,q);
+        b.output(r);
+        let c = b.finish();
+
+        let stats: CircuitStats = serde_json::from_str(&c.stats_json()).unwrap();
+        assert_eq!(stats, c.stats());
+        assert_eq!(stats.ninputs, 3);
+        assert_eq!(stats.nproj, 1);
+        assert_eq!(stats.nhalfgate, 1);
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.width, 3);
+        assert_eq!(stats.moduli, vec![2,3]);
+        // 2 for the projection, 2 for the half gate and 2 for the output
+        assert_eq!(stats.ciphertexts, 6);
+        assert_eq!(stats.free_gate_ratio, c.free_gate_ratio());
+    }
+//}}}
+    #[test] // probably_equivalent {{{
+    fn probably_equivalent() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..16 {
+            let c = random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            assert!(c.probably_equivalent(&c, 16, &mut rng));
+            assert!(c.probably_equivalent(&c.reorder_for_locality().0, 16, &mut rng));
+            assert!(c.probably_equivalent(&c.pad_to(c.num_nonfree_gates + 4), 16, &mut rng));
+
+            let mut fewer_outputs = c.clone();
+            fewer_outputs.output_refs.pop();
+            assert!(!c.probably_equivalent(&fewer_outputs, 16, &mut rng));
+        }
+
+        let mut b = Builder::new();
+        let x = b.input(5);
+        let y = b.input(5);
+        let z = b.add(x,y);
+        b.output(z);
+        let c = b.finish();
+
+        // subtracting agrees with adding only when y is 0
+        let mut broken = c.clone();
+        broken.gates[2] = Gate::Sub { xref: 0, yref: 1 };
+        assert!(!c.probably_equivalent(&broken, 64, &mut rng));
+    }
+//}}}
+    #[test] // critical_hash_path {{{
+    fn critical_hash_path() {
+        let mut b = Builder::new();
+        let x = b.input(3);
+        let y = b.input(3);
+        // a long chain of free gates weighs nothing
+        let mut s = x;
+        for _ in 0..10 {
+            s = b.add(s,y);
+        }
+        let p = b.mod_change(s,5);          // 1
+        let q = b.mod_change(y,5);          // 1
+        let h = b.half_gate(p,q);           // 1 + 2
+        let t = b.yao(h, x, 2, (0..5).map(|a| (0..3).map(|c| (a+c) % 2).collect()).collect()); // 3 + 1
+        b.output(t);
+        b.output(s);
+        let c = b.finish();
+        assert_eq!(c.critical_hash_path(), 4);
+        assert!(c.stats().depth > 10);
+
+        // the half gates outweigh the projections on the other output
+        let mut b = Builder::new();
+        let x = b.input(2);
+        let y = b.input(2);
+        let mut z = x;
+        for _ in 0..3 {
+            z = b.and(z,y);
+        }
+        let w = b.negate(x);
+        let w = b.negate(w);
+        b.output(w);
+        b.output(z);
+        let c = b.finish();
+        assert_eq!(c.critical_hash_path(), 6);
+    }
+//}}}
+    #[test] // embed {{{
+    fn embed() {
+        let mut rng = rand::thread_rng();
+        let q = 2 + rng.gen_u16() % 15;
+        let n = 1 + rng.gen_usize() % 4;
+        let Q = (q as u128).pow(n as u32);
+
+        let mut b = Builder::new();
+        let xs = b.inputs(n,q);
+        let ys = b.inputs(n,q);
+        let mut ones = vec![b.constant(0,q); n];
+        ones[0] = b.constant(1,q);
+        let zs = b.addition_no_carry(&xs, &ys);
+        let zs = b.addition_no_carry(&zs, &ones);
+        b.outputs(&zs);
+        let adder = b.finish();
+
+        // (x + y + 1) + z + 1
+        let mut b = Builder::new();
+        let xs = b.inputs(n,q);
+        let ys = b.inputs(n,q);
+        let zs = b.inputs(n,q);
+        let mut inps = xs.clone();
+        inps.extend(&ys);
+        let ss = b.embed(&adder, &inps);
+        let mut inps = ss.clone();
+        inps.extend(&zs);
+        let ts = b.embed(&adder, &inps);
+        b.outputs(&ts);
+        let c = b.finish();
+        assert_eq!(c.num_nonfree_gates, 2 * adder.num_nonfree_gates);
+
+        let (en, de, ev) = crate::garble::garble(&c);
+        for _ in 0..16 {
+            let vals = (0..3).map(|_| rng.gen_u128() % Q).collect_vec();
+            let inps = vals.iter().flat_map(|&v| numbers::as_base_q(v,q,n)).collect_vec();
+            let should_be = numbers::as_base_q((vals.iter().sum::<u128>() + 2) % Q, q, n);
+            assert_eq!(c.eval(&inps), should_be);
+            assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+        }
+    }
+//}}}
+    #[test] // fix_inputs {{{
+    fn fix_inputs() {
+        let mut rng = rand::thread_rng();
+        let q = rng.gen_modulus();
+        let mut b = Builder::new();
+        let x = b.input(q);
+        let k = b.constant(1,q);
+        let y = b.input(q);
+        let z = b.add(x,y);
+        let z = b.add(z,k);
+        b.output(z);
+        let c = b.finish();
+
+        let v = rng.gen_u16() % q;
+        let fixed = c.fix_inputs(&[(0,v)]);
+        assert_eq!(fixed.ninputs(), 1);
+        assert_eq!(fixed.const_refs.len(), 2);
+        for _ in 0..16 {
+            let y = rng.gen_u16() % q;
+            assert_eq!(fixed.eval(&[y]), c.eval(&[v,y]));
+        }
+        let (en, de, ev) = crate::garble::garble(&fixed);
+        let y = rng.gen_u16() % q;
+        assert_eq!(de.decode(&ev.eval(&fixed, &en.encode(&[y]))), c.eval(&[v,y]));
+
+        // the remaining inputs keep their order
+        let c = random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+        let inps = (0..4).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+        let fixed = c.fix_inputs(&[(2,inps[2]), (0,inps[0])]);
+        assert_eq!(fixed.ninputs(), 2);
+        assert_eq!(fixed.eval(&[inps[1], inps[3]]), c.eval(&inps));
+        assert_eq!(fixed.fix_inputs(&[]), fixed);
+    }
+//}}}
+    #[test] // output_named {{{
+    fn output_named() {
+        let mut b = Builder::new();
+        let x = b.input(5);
+        let y = b.input(7);
+        b.output_named(x, "x");
+        b.output(y);
+        b.output_named(y, "y");
+        assert_eq!(b.try_output_named(x, "y"), Err(BuildError::DuplicateOutputName { name: "y".to_string() }));
+        let c = b.finish();
+        assert_eq!(c.noutputs(), 3);
+        assert_eq!(c.output_names["x"], 0);
+        assert_eq!(c.output_names["y"], 2);
+        assert_eq!(c, Circuit::from_str(&c.to_string()).unwrap());
+        assert_eq!(c, Circuit::from_compact_bytes(&c.to_compact_bytes()).unwrap());
+    }
+//}}}
+    #[test] // max_yao_size {{{
+    fn max_yao_size() {
+        let tt = |p: u16, q: u16| (0..p).map(|a| (0..q).map(|b| (a+b) % 7).collect()).collect();
+
+        let mut b = Builder::new().with_max_yao_size(100);
+        let x = b.input(257);
+        let y = b.input(257);
+        let u = b.input(11);
+        let v = b.input(9);
+        assert_eq!(b.try_yao(x, y, 7, tt(257,257)), Err(BuildError::YaoTooLarge { size: 257*257 - 1 }));
+        assert_eq!(b.borrow_circ().num_nonfree_gates, 0);
+        // 11*9 - 1 ciphertexts is exactly at the limit
+        assert!(b.try_yao(u, v, 7, tt(11,9)).is_ok());
+        assert!(b.try_yao(u, y, 7, tt(11,257)).is_err());
+
+        let mut b = Builder::new();
+        let x = b.input(257);
+        let y = b.input(257);
+        assert!(b.try_yao(x, y, 7, tt(257,257)).is_ok());
+    }
+//}}}
+    #[test] // try_input {{{
+    fn try_input() {
+        let mut b = Builder::new();
+        assert!(b.try_input(2).is_ok());
+        assert!(b.try_input(113).is_ok());
+        assert_eq!(b.try_input(0), Err(BuildError::SmallModulus { modulus: 0 }));
+        assert_eq!(b.try_input(1), Err(BuildError::SmallModulus { modulus: 1 }));
+        assert_eq!(b.borrow_circ().ninputs(), 2);
+    }
+//}}}
+    #[test] // subcircuit {{{
+    fn subcircuit() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let c = random_circuit(&mut rng, 6, 32, &[2,3,5,7]);
+            let root = rng.gen_usize() % c.gates.len();
+            let sub = c.subcircuit(root);
+
+            // the full circuit with root as its only output
+            let mut full = c.clone();
+            full.output_refs = vec![root];
+            full.output_modes = Vec::new();
+            let deps = full.output_dependencies(0);
+            assert_eq!(sub.ninputs(), deps.len());
+            assert!(sub.gates.len() <= root + 1);
+
+            for _ in 0..4 {
+                let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+                let sub_inps = deps.iter().map(|&i| inps[i]).collect_vec();
+                assert_eq!(sub.eval(&sub_inps), full.eval(&inps));
+            }
+
+            let (en, de, ev) = crate::garble::garble(&sub);
+            let inps = (0..sub.ninputs()).map(|i| rng.gen_u16() % sub.input_mod(i)).collect_vec();
+            assert_eq!(de.decode(&ev.eval(&sub, &en.encode(&inps))), sub.eval(&inps));
+        }
+    }
+//}}}
+    #[test] // reorder_for_locality {{{
+    fn reorder_for_locality() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let c = random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let (d, perm) = c.reorder_for_locality();
+            assert_eq!(d.gates.len(), c.gates.len());
+            assert_eq!(perm.iter().cloned().sorted().collect_vec(), (0..c.gates.len()).collect_vec());
+            for (r, g) in d.gates.iter().enumerate() {
+                assert!(g.args().iter().all(|&x| x < r));
+            }
+            for (old, &new) in perm.iter().enumerate() {
+                assert_eq!(c.modulus(old), d.modulus(new));
+            }
+            for _ in 0..4 {
+                let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+                assert_eq!(d.eval(&inps), c.eval(&inps));
+            }
+
+            let (en, de, ev) = crate::garble::garble(&d);
+            let inps = (0..d.ninputs()).map(|i| rng.gen_u16() % d.input_mod(i)).collect_vec();
+            assert_eq!(de.decode(&ev.eval(&d, &en.encode(&inps))), c.eval(&inps));
+        }
+    }
+//}}}
+    #[test] // simplify {{{
+    fn simplify() {
+        let mut rng = rand::thread_rng();
+        let mut b = Builder::new();
+        let x = b.input(7);
+        let y = b.proj(x, 5, (0..7).map(|i| (i * 3) % 5).collect());
+        let z = b.proj(y, 3, vec![2,0,1,1,0]);
+        let w = b.proj(z, 4, vec![3,1,2]);
+        let u = b.proj(x, 6, (0..7).map(|i| i % 6).collect());
+        let v = b.proj(u, 2, (0..6).map(|i| i % 2).collect());
+        b.output(w);
+        b.output(u);
+        b.output(v);
+        let c = b.finish();
+
+        // the chain x -> y -> z -> w collapses, but u is an output so it stays
+        let d = c.simplify();
+        assert_eq!(d.num_nonfree_gates, 3);
+        assert_eq!(d.gates.len(), 4);
+        assert_eq!(d.output_moduli(), c.output_moduli());
+        for i in 0..7 {
+            assert_eq!(d.eval(&[i]), c.eval(&[i]));
+        }
+        let (en, de, ev) = crate::garble::garble(&d);
+        for i in 0..7 {
+            assert_eq!(de.decode(&ev.eval(&d, &en.encode(&[i]))), c.eval(&[i]));
+        }
+
+        for _ in 0..100 {
+            let c = random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let d = c.simplify();
+            assert!(d.num_nonfree_gates <= c.num_nonfree_gates);
+            assert!(c.probably_equivalent(&d, 4, &mut rng));
+        }
+    }
+//}}}
+    #[test] // output_dependencies {{{
+    fn output_dependencies() {
+        let mut b = Builder::new();
+        let xs = b.inputs(4, 3);
+        let c = b.constant(1, 3);
+        let y = b.add(xs[2], c);
+        let z = b.half_gate(xs[0], y);
+        let w = b.sub(xs[1], xs[3]);
+        b.output(z);
+        b.output(w);
+        b.output(c);
+        let circ = b.finish();
+        assert_eq!(circ.output_dependencies(0), vec![0,2]);
+        assert_eq!(circ.output_dependencies(1), vec![1,3]);
+        assert!(circ.output_dependencies(2).is_empty());
+    }
+//}}}
+    #[test] // deduplicate_structure {{{
+    fn deduplicate_structure() {
+        let mut b = Builder::new();
+        let xs = b.inputs(8, 2);
+        let ys = b.inputs(8, 2);
+        let us = b.inputs(8, 2);
+        let vs = b.inputs(8, 2);
+        let (zs1, c1) = b.addition(&xs, &ys);
+        let (zs2, c2) = b.addition(&us, &vs);
+        let w = b.input(3);
+        let w2 = b.half_gate(w,w);
+        b.outputs(&zs1);
+        b.outputs(&zs2);
+        b.output(w2);
+        let circ = b.finish();
+
+        let (dedup, ids) = circ.deduplicate_structure();
+        assert_eq!(ids.len(), circ.gates.len());
+
+        // the two adders are structurally identical
+        for (&z1, &z2) in zs1.iter().zip(zs2.iter()) {
+            assert_eq!(ids[z1], ids[z2]);
+        }
+        assert_eq!(ids[c1], ids[c2]);
+
+        // but differ from the mod 3 half gate
+        assert!(zs1.iter().all(|&z| ids[z] != ids[w2]));
+
+        assert!(dedup.gates.len() < circ.gates.len() / 2);
+        assert_eq!(dedup.ninputs(), 2);
+        assert_eq!(dedup.noutputs(), circ.noutputs());
+        assert_eq!(dedup.num_nonfree_gates,
+                   dedup.gates.iter().filter(|g| !g.is_free()).count());
+
+        // the collapsed circuit is itself well formed
+        let inps = vec![0; dedup.ninputs()];
+        dedup.eval(&inps);
+    }
+//}}}
+    #[test] // dot {{{
+    fn dot() {
+        let mut rng = rand::thread_rng();
+        let p = rng.gen_prime();
+        let n = 1 + rng.gen_usize() % 16;
+        let mut b = Builder::new();
+        let xs = b.inputs(n, p);
+        let ys = b.inputs(n, p);
+        let z = b.dot(&xs, &ys, p);
+        b.output(z);
+        let c = b.finish();
+        for _ in 0..16 {
+            let inps = (0..2*n).map(|_| rng.gen_u16() % p).collect_vec();
+            let should_be = (0..n).fold(0, |acc, i| (acc + inps[i] * inps[n+i]) % p);
+            assert_eq!(c.eval(&inps)[0], should_be);
+        }
+    }
+//}}}
+    #[test] // add_modulus_mismatch {{{
+    #[should_panic(expected = "add: modulus mismatch 3 vs 5")]
+    fn add_modulus_mismatch() {
+        let mut b = Builder::new();
+        let x = b.input(3);
+        let y = b.input(5);
+        b.add(x,y);
+    }
+//}}}
+    #[test] // sub_modulus_mismatch {{{
+    #[should_panic(expected = "sub: modulus mismatch 3 vs 5")]
+    fn sub_modulus_mismatch() {
+        let mut b = Builder::new();
+        let x = b.input(3);
+        let y = b.input(5);
+        b.sub(x,y);
+    }
+//}}}
+    #[test] // add_many_modulus_mismatch {{{
+    #[should_panic(expected = "add_many: modulus mismatch 3 vs 5")]
+    fn add_many_modulus_mismatch() {
+        let mut b = Builder::new();
+        let xs = b.inputs(4, 3);
+        let y = b.input(5);
+        b.add_many(&[xs[0], xs[1], y, xs[2]]);
+    }
+//}}}
+    #[test] // reject_small_moduli {{{
+    fn reject_small_moduli() {
+        // the message of the panic raised by f, which must panic
+        fn panic_message<F: FnOnce() -> R + std::panic::UnwindSafe, R>(f: F) -> String {
+            match std::panic::catch_unwind(f) {
+                Ok(_) => panic!("expected a panic"),
+                Err(e) => e.downcast_ref::<String>().cloned().unwrap_or_default(),
+            }
+        }
+        for q in 0..2 {
+            let expected = |method: &str| format!("[builder.{}] modulus must be at least 2, got {}", method, q);
+            assert_eq!(panic_message(|| Builder::new().input(q)), expected("input"));
+            assert_eq!(panic_message(|| Builder::new().inputs(3, q)), expected("inputs"));
+            assert_eq!(panic_message(|| Builder::new().constant(0, q)), expected("constant"));
+            assert_eq!(panic_message(|| Builder::new().secret_constant(0, q)), expected("secret_constant"));
+            assert_eq!(panic_message(|| {
+                let mut b = Builder::new();
+                let x = b.input(2);
+                b.proj(x, q, vec![0,0])
+            }), expected("proj"));
+            assert_eq!(panic_message(|| {
+                let mut b = Builder::new();
+                let x = b.input(2);
+                let y = b.input(2);
+                b.yao(x, y, q, vec![vec![0,0], vec![0,0]])
+            }), expected("yao"));
+            assert_eq!(panic_message(|| {
+                let mut b = Builder::new();
+                let x = b.input(2);
+                b.mod_change(x, q)
+            }), expected("mod_change"));
+        }
+    }
+//}}}
 
 }
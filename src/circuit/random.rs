@@ -0,0 +1,108 @@
+//! Random circuit generation, for fuzzing the garbling scheme and gadgets.
+
+use crate::circuit::{Builder, Circuit};
+use crate::util::RngExt;
+use rand::Rng;
+
+/// Build a random, well-formed circuit with `ninputs` inputs followed by `ngates` random
+/// gates, with all moduli drawn from `moduli`. Every wire which is not used by a later
+/// gate becomes an output.
+pub fn random_circuit<R: Rng>(rng: &mut R, ninputs: usize, ngates: usize, moduli: &[u16]) -> Circuit {
+    assert!(ninputs > 0, "[random_circuit] need at least one input");
+    assert!(!moduli.is_empty(), "[random_circuit] need at least one modulus");
+
+    let mut b = Builder::new();
+
+    for _ in 0..ninputs {
+        let q = moduli[rng.gen_usize() % moduli.len()];
+        b.input(q);
+    }
+
+    for _ in 0..ngates {
+        let nrefs = b.circ.gates.len();
+        let x = rng.gen_usize() % nrefs;
+        let xmod = b.modulus(x);
+        let q = moduli[rng.gen_usize() % moduli.len()];
+
+        // a random wire with the same modulus as x, possibly x itself
+        let same_mod = (0..nrefs).filter(|&r| b.modulus(r) == xmod).collect::<Vec<_>>();
+        let y = same_mod[rng.gen_usize() % same_mod.len()];
+
+        match rng.gen_usize() % 7 {
+            0 => {
+                let c = rng.gen_u16() % q;
+                b.secret_constant(c, q);
+            }
+            1 => { b.add(x,y); }
+            2 => { b.sub(x,y); }
+            3 => {
+                let c = rng.gen_u16() % xmod;
+                b.cmul(x,c);
+            }
+            4 => {
+                let tt = (0..xmod).map(|_| rng.gen_u16() % q).collect();
+                b.proj(x, q, tt);
+            }
+            5 => {
+                let y = rng.gen_usize() % nrefs;
+                let ymod = b.modulus(y);
+                let tt = (0..xmod).map(|_| {
+                    (0..ymod).map(|_| rng.gen_u16() % q).collect()
+                }).collect();
+                b.yao(x, y, q, tt);
+            }
+            _ => {
+                // half gates with unequal moduli need the smaller modulus to be at most 8
+                let z = rng.gen_usize() % nrefs;
+                let zmod = b.modulus(z);
+                if xmod == zmod || xmod.min(zmod) <= 8 {
+                    b.half_gate(x,z);
+                } else {
+                    b.half_gate(x,y);
+                }
+            }
+        }
+    }
+
+    let mut used = vec![false; b.circ.gates.len()];
+    for g in b.circ.gates.iter() {
+        for x in g.args() {
+            used[x] = true;
+        }
+    }
+    for (r, &u) in used.iter().enumerate() {
+        if !u {
+            b.output(r);
+        }
+    }
+
+    b.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::garble::garble;
+    use itertools::Itertools;
+    use rand::thread_rng;
+
+    #[test] // random_circuits_garble {{{
+    fn random_circuits_garble() {
+        let mut rng = thread_rng();
+        let moduli = [2,3,5,7,8];
+        for _ in 0..1000 {
+            let ninputs = 1 + rng.gen_usize() % 4;
+            let ngates = rng.gen_usize() % 16;
+            let c = random_circuit(&mut rng, ninputs, ngates, &moduli);
+            assert_eq!(c.gates.len(), ninputs + ngates);
+            assert!(c.noutputs() > 0);
+
+            let (en, de, ev) = garble(&c);
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            let xs = en.encode(&inps);
+            let ys = ev.eval(&c, &xs);
+            assert_eq!(de.decode(&ys), c.eval(&inps), "circ={:?}", c);
+        }
+    }
+//}}}
+}
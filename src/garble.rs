@@ -1,13 +1,20 @@
 //! Structs and functions for creating, and evaluating garbled circuits.
 
-use crate::circuit::{Circuit, Ref, Gate, Id};
+use crate::aes::{AES, Cipher, TweakOffset};
+use crate::circuit::{Circuit, Ref, Gate, Id, OutputMode};
+use crate::numbers;
 use crate::wire::Wire;
+use bincode::Options;
 use itertools::Itertools;
+use rand::RngCore;
 use rand::rngs::ThreadRng;
 use serde_derive::{Serialize, Deserialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Range;
 
 pub mod operations;
+pub mod stream;
 
 /// The ciphertext created by a garbled gate.
 pub type GarbledGate = Vec<u128>;
@@ -20,7 +27,9 @@ pub struct Encoder {
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Decoder {
-    outputs : Vec<Vec<u128>>
+    outputs       : Vec<Vec<u128>>, // empty for labeled outputs
+    output_moduli : Vec<u16>,
+    output_names  : HashMap<String, usize>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -29,18 +38,115 @@ pub struct Evaluator {
     consts : Vec<Wire>,
 }
 
+/// Encoded input wires tagged with the input index and modulus each is meant for, so that
+/// the evaluator can check them with `verify_against` before evaluating.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EncodedInputs {
+    pub inputs: Vec<EncodedInput>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EncodedInput {
+    pub id: Id,
+    pub modulus: u16,
+    pub wire: Wire,
+}
+
+/// A contiguous run of output wires holding a single integer, see
+/// `Decoder::decode_structured`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputBundle {
+    pub start: usize,
+    pub len: usize,
+    pub base: OutputBase,
+}
+
+/// How the output wires of an `OutputBundle` represent an integer. The moduli are those of
+/// the wires themselves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputBase {
+    /// Mixed radix digits, least significant first.
+    MixedRadix,
+    /// Residues modulo pairwise coprime moduli.
+    Crt,
+}
+
+/// Where the ciphertexts of a garbled gate, or a const wire, sit in the output of
+/// `Evaluator::ciphertext_bytes`, see `Evaluator::layout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GateLayout {
+    /// The gate id, or the const id for `GateKind::Const`.
+    pub id: Id,
+    pub kind: GateKind,
+    pub offset: usize,
+    pub len: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GateKind {
+    Proj,
+    Yao,
+    HalfGate,
+    Const,
+}
+
 /// Garbler is an iterator for streaming `GarbledGate`s, and producing constant wires,
 /// `Encoder` and `Decoder`. It is intended to be used via its `Iterator` instance, during
 /// which it produces wirelabels for all internal wires while creating `GarbledGate` for
 /// each gate which requires ciphertexts.
-pub struct Garbler<'a> {
+///
+/// By default randomness comes from `rand::thread_rng`, but any `RngCore` may be supplied
+/// using `Garbler::with_rng`. Similarly, hashing uses `AES` unless another `Cipher` is
+/// supplied using `Garbler::with_cipher`, and every tweak is offset by the base set with
+/// `Garbler::with_tweak_base`, zero by default.
+pub struct Garbler<'a, R: RngCore = ThreadRng> {
     circuit: &'a Circuit,
     wires: Vec<Wire>,
     inputs: Vec<Wire>,
     consts: Vec<Wire>,
     deltas: HashMap<u16, Wire>,
     current_wire: Ref,
-    rng: ThreadRng,
+    rng: R,
+    cipher: TweakOffset<&'a dyn Cipher>,
+    checked: bool,
+}
+
+/// The ways in which an `Evaluator` can fail to match a `Circuit`, see
+/// `Evaluator::is_consistent_with`.
+#[derive(Debug, PartialEq)]
+pub enum ConsistencyError {
+    GateCount { expected: usize, got: usize },
+    GateSize { id: Id, expected: usize, got: usize },
+    ConstCount { expected: usize, got: usize },
+}
+
+impl std::fmt::Display for ConsistencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConsistencyError::GateCount { expected, got } =>
+                write!(f, "expected {} garbled gates but got {}", expected, got),
+            ConsistencyError::GateSize { id, expected, got } =>
+                write!(f, "garbled gate {} should have {} ciphertexts but has {}", id, expected, got),
+            ConsistencyError::ConstCount { expected, got } =>
+                write!(f, "expected {} constants but got {}", expected, got),
+        }
+    }
+}
+
+impl std::error::Error for ConsistencyError {}
+
+/// The secret state held by a `Garbler`, see `Garbler::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GarblerStats {
+    pub ndeltas: usize,
+    pub ninputs: usize,
+    pub nconsts: usize,
+    /// The number of wires generated so far.
+    pub nwires: usize,
+    /// The number of decoding tables `Garbler::decoder` will create.
+    pub noutput_tables: usize,
+    /// An estimate of the memory used by all of the above.
+    pub bytes: usize,
 }
 
 /// Convenience function to garble directly with no streaming.
@@ -53,18 +159,107 @@ pub fn garble(c: &Circuit) -> (Encoder, Decoder, Evaluator) {
     (en, de, ev)
 }
 
+/// Like `garble`, but every tweak is offset by `tweak_base` with a `TweakOffset` cipher,
+/// to separate this session from others garbled under the same key. The evaluator must
+/// use `Evaluator::eval_with_tweak_base` with the same base, and the outputs must be
+/// decoded with `Decoder::try_decode_with_cipher` and a matching `TweakOffset`.
+pub fn garble_with_tweak_base(c: &Circuit, tweak_base: u128) -> (Encoder, Decoder, Evaluator) {
+    let mut garbler = Garbler::new(c).with_tweak_base(tweak_base);
+    let en     = garbler.encoder();
+    let gates  = garbler.by_ref().collect();
+    let ev     = Evaluator::new(gates, garbler.consts());
+    let de     = garbler.decoder().unwrap();
+    (en, de, ev)
+}
+
+/// The error returned by `garble_with_memory_limit` when a circuit is too large.
+#[derive(Debug)]
+pub struct MemoryError {
+    pub estimate: usize,
+    pub limit: usize,
+}
+
+impl std::fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "garbling needs an estimated {} bytes, exceeding the limit of {}",
+               self.estimate, self.limit)
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+/// Like `garble`, but fails without allocating anything if
+/// `Circuit::estimated_garble_memory` exceeds `limit` bytes.
+pub fn garble_with_memory_limit(c: &Circuit, limit: usize)
+    -> Result<(Encoder, Decoder, Evaluator), MemoryError>
+{
+    let estimate = c.estimated_garble_memory();
+    if estimate > limit {
+        return Err(MemoryError { estimate, limit });
+    }
+    Ok(garble(c))
+}
+
+/// Garble `c` and encode `inputs`, the values of all of its inputs, returning everything
+/// the evaluator needs as a single blob to be read by `eval_from_bytes`, and the decoder
+/// for the garbler to keep.
+pub fn garble_to_bytes(c: &Circuit, inputs: &[u16]) -> Result<(Vec<u8>, Decoder), failure::Error> {
+    if inputs.len() != c.ninputs() {
+        return Err(failure::format_err!("[garble_to_bytes] expected {} inputs but got {}",
+                                        c.ninputs(), inputs.len()));
+    }
+    if let Some(i) = (0..inputs.len()).find(|&i| inputs[i] >= c.input_mod(i)) {
+        return Err(failure::format_err!("[garble_to_bytes] input {} is out of range for modulus {}",
+                                        i, c.input_mod(i)));
+    }
+    let (en, de, ev) = garble(c);
+    let xs = en.encode(inputs);
+    let bs = bincode::serialize(&(ev, xs)).expect("couldn't serialize garbling");
+    Ok((bs, de))
+}
+
+/// Evaluate `c` on a blob produced by `garble_to_bytes`, returning the output wires.
+pub fn eval_from_bytes(c: &Circuit, bs: &[u8]) -> Result<Vec<Wire>, failure::Error> {
+    let (ev, xs): (Evaluator, Vec<Wire>) = bincode::deserialize(bs)
+        .map_err(|_| failure::err_msg("error decoding garbling from bytes"))?;
+    ev.is_consistent_with(c)?;
+    if xs.len() != c.ninputs() {
+        return Err(failure::format_err!("[eval_from_bytes] expected {} inputs but got {}",
+                                        c.ninputs(), xs.len()));
+    }
+    Ok(ev.eval(c, &xs))
+}
+
+/// Whether `a` and `b` hold exactly the same garbled gates and const wires, for checking
+/// that two ways of garbling a circuit agree when given the same randomness.
+pub fn garblings_identical(a: &Evaluator, b: &Evaluator) -> bool {
+    a.gates == b.gates && a.consts == b.consts
+}
+
 impl <'a> Garbler<'a> {
     pub fn new(circuit: &'a Circuit) -> Garbler {
-        let mut rng = rand::thread_rng();
+        Self::with_rng(circuit, rand::thread_rng())
+    }
+}
+
+impl <'a, R: RngCore> Garbler<'a, R> {
+    /// Create a `Garbler` which draws all of its randomness from `rng`.
+    pub fn with_rng(circuit: &'a Circuit, rng: R) -> Garbler<'a, R> {
+        Self::with_deltas(circuit, rng, HashMap::new())
+    }
 
-        let mut deltas  = HashMap::new();
+    /// Create a `Garbler` which garbles with the deltas in `deltas`, for instance those of
+    /// an earlier `Garbler`, drawing deltas only for the moduli of `circuit` missing from
+    /// it, and all other randomness, from `rng`.
+    pub fn with_deltas(circuit: &'a Circuit, mut rng: R, mut deltas: HashMap<u16, Wire>) -> Garbler<'a, R> {
         let mut inputs  = Vec::new();
         let mut consts  = Vec::new();
 
         // initialize deltas
         for &m in circuit.gate_moduli.iter().unique() {
-            let w = Wire::rand_delta(&mut rng, m);
-            deltas.insert(m, w);
+            if !deltas.contains_key(&m) {
+                deltas.insert(m, Wire::rand_delta(&mut rng, m));
+            }
         }
 
         // initialize inputs
@@ -83,7 +278,68 @@ impl <'a> Garbler<'a> {
 
         let wires = Vec::with_capacity(circuit.gates.len());
 
-        Garbler { circuit, wires, inputs, consts, deltas, current_wire: 0, rng }
+        Garbler { circuit, wires, inputs, consts, deltas, current_wire: 0, rng, cipher: TweakOffset::wrap(&AES, 0), checked: false }
+    }
+
+    /// Take `input_zeros` as the zero wires of the first inputs. Creating this `Garbler`
+    /// with `with_deltas` and the deltas of an earlier `Garbler`, and passing its
+    /// `labeled_outputs` and `tweak_end` here, lets the evaluator feed that circuit's
+    /// labeled outputs directly into this one, while `tweak_base` makes this garbling
+    /// continue the tweaks of the earlier one rather than reusing them under the same
+    /// deltas. Evaluate and decode as for `with_tweak_base`.
+    pub fn chain(mut self, input_zeros: &[Wire], tweak_base: u128) -> Self {
+        assert!(input_zeros.len() <= self.inputs.len(), "[garbler.chain] too many input wires");
+        for (i, w) in input_zeros.iter().enumerate() {
+            assert_eq!(w.modulus(), self.circuit.input_mod(i), "[garbler.chain] input {} has the wrong modulus", i);
+            self.inputs[i] = w.clone();
+        }
+        self.with_tweak_base(tweak_base)
+    }
+
+    /// Make sure there is a delta for each of `moduli`, drawing those which are missing,
+    /// so that they can be agreed on in an offline phase, or used by a later `Garbler`
+    /// through `with_deltas`, before garbling. Garbling uses these deltas.
+    pub fn preprocess_deltas(&mut self, moduli: &[u16]) {
+        for &m in moduli {
+            if !self.deltas.contains_key(&m) {
+                let d = Wire::rand_delta(&mut self.rng, m);
+                self.deltas.insert(m, d);
+            }
+        }
+    }
+
+    /// Check every garbled Yao gate with `operations::check_yao` as it is created, panicking
+    /// if the evaluator would not recover the intended output label for some input. This
+    /// is slow, and intended for debugging custom truth tables.
+    pub fn checked(mut self) -> Self {
+        self.checked = true;
+        self
+    }
+
+    /// Use `cipher` rather than `AES` for all hashing done by this `Garbler`.
+    pub fn with_cipher(mut self, cipher: &'a dyn Cipher) -> Self {
+        self.cipher = TweakOffset::wrap(cipher, self.cipher.base());
+        self
+    }
+
+    /// Offset every tweak by `tweak_base`. The evaluator must use
+    /// `Evaluator::eval_with_tweak_base` with the same base, and the outputs must be
+    /// decoded with `Decoder::try_decode_with_cipher` and a matching `TweakOffset`.
+    pub fn with_tweak_base(mut self, tweak_base: u128) -> Self {
+        self.cipher = TweakOffset::wrap(*self.cipher.inner(), tweak_base);
+        self
+    }
+
+    /// The base every tweak of this garbling is offset by.
+    pub fn tweak_base(&self) -> u128 {
+        self.cipher.base()
+    }
+
+    /// One past the largest tweak this garbling can use, to pass to `chain` so that the
+    /// next garbling starts where this one stops.
+    pub fn tweak_end(&self) -> u128 {
+        let n = std::cmp::max(self.circuit.gates.len(), self.circuit.noutputs()) as u128;
+        self.tweak_base().wrapping_add(n << 64)
     }
 
     /// Extract the const wires from the `Garbler`.
@@ -92,6 +348,126 @@ impl <'a> Garbler<'a> {
         operations::encode_consts(cs, &self.consts, &self.deltas)
     }
 
+    pub fn deltas(&self) -> &HashMap<u16, Wire> {
+        &self.deltas
+    }
+
+    /// Report how much secret state the `Garbler` is holding.
+    pub fn stats(&self) -> GarblerStats {
+        let label_bytes = |w: &Wire| std::mem::size_of::<Wire>() + match w {
+            Wire::Mod2 { .. } => 0,
+            Wire::ModN { ds, .. } => ds.len() * std::mem::size_of::<u16>(),
+        };
+        let tables = (0..self.circuit.noutputs())
+            .filter(|&i| self.output_mode(i) == OutputMode::Decoded)
+            .collect_vec();
+
+        let mut bytes = 0;
+        bytes += self.deltas.values().map(label_bytes).sum::<usize>();
+        bytes += self.inputs.iter().map(label_bytes).sum::<usize>();
+        bytes += self.consts.iter().map(label_bytes).sum::<usize>();
+        bytes += self.wires.iter().map(label_bytes).sum::<usize>();
+        bytes += tables.iter().map(|&i| {
+            self.circuit.modulus(self.circuit.output_refs[i]) as usize * std::mem::size_of::<u128>()
+        }).sum::<usize>();
+
+        GarblerStats {
+            ndeltas: self.deltas.len(),
+            ninputs: self.inputs.len(),
+            nconsts: self.consts.len(),
+            nwires: self.wires.len(),
+            noutput_tables: tables.len(),
+            bytes,
+        }
+    }
+
+    /// Describe every delta, and the zero wire of every gate garbled so far, as digits,
+    /// one per line. Intended for teaching and debugging with tiny circuits.
+    ///
+    /// **Warning: the dump contains every secret of the garbling.** Anyone holding it can
+    /// decode every wire of the evaluation, including the inputs.
+    #[cfg(feature = "debug-dump")]
+    pub fn debug_dump(&self) -> String {
+        use std::fmt::Write;
+        let mut s = String::new();
+        for q in self.deltas.keys().sorted() {
+            writeln!(s, "delta mod {}: {:?}", q, self.deltas[q].digits()).unwrap();
+        }
+        for (i, w) in self.wires.iter().enumerate() {
+            writeln!(s, "gate {} mod {}: {:?} zero={:?}", i, w.modulus(),
+                     self.circuit.gates[i], w.digits()).unwrap();
+        }
+        s
+    }
+
+    pub fn output_mode(&self, i: usize) -> OutputMode {
+        self.circuit.output_mode(i)
+    }
+
+    /// Garble the gates in `range` on their own, so that a large circuit can be garbled in
+    /// pieces without holding every wire. `carry_in` holds the zero wires of
+    /// `circuit.segment_boundary(range.start)`, in order, and the zero wires of
+    /// `circuit.segment_boundary(range.end)` are returned alongside the garbled gates, ready
+    /// to be passed to the next segment. Garbling consecutive segments from 0 gives the same
+    /// gates as the iterator would, given the same randomness. This is independent of the
+    /// iterator interface, so `decoder` and `labeled_outputs` are not available for
+    /// circuits garbled this way, but the zero wires of the outputs are in the final boundary.
+    pub fn garble_segment(&mut self, range: Range<usize>, carry_in: &[Wire]) -> (Vec<GarbledGate>, Vec<Wire>) {
+        let boundary = self.circuit.segment_boundary(range.start);
+        assert_eq!(boundary.len(), carry_in.len(), "[garbler.garble_segment] expected {} boundary wires", boundary.len());
+        assert!(range.end <= self.circuit.gates.len(), "[garbler.garble_segment] range out of bounds");
+        let mut wires: HashMap<Ref, Wire> = boundary.into_iter().zip(carry_in.iter().cloned()).collect();
+        let mut gates = Vec::new();
+        for r in range.clone() {
+            let (w, g) = self.garble_gate(r, |x| &wires[&x]);
+            wires.insert(r, w);
+            gates.extend(g);
+        }
+        let carry_out = self.circuit.segment_boundary(range.end).iter().map(|r| wires[r].clone()).collect();
+        (gates, carry_out)
+    }
+
+    // garble the gate at r, looking up the zero wires of its arguments with wire
+    fn garble_gate<'w>(&mut self, r: Ref, wire: impl Fn(Ref) -> &'w Wire) -> (Wire, Option<GarbledGate>) {
+        let q = self.circuit.modulus(r);
+        match self.circuit.gates[r] {
+            Gate::Input { id } => (self.inputs[id].clone(), None),
+            Gate::Const { id } => (self.consts[id].clone(), None),
+
+            Gate::Add { xref, yref } => (wire(xref).plus(wire(yref)),  None),
+            Gate::Sub { xref, yref } => (wire(xref).minus(wire(yref)), None),
+            Gate::Cmul { xref, c }   => (wire(xref).cmul((c % q as u32) as u16), None),
+
+            Gate::Proj { xref, ref tt, .. } =>
+                operations::garble_projection(wire(xref), q, tt, r, &self.deltas, &self.cipher),
+
+            Gate::Yao { xref, yref, ref tt, .. } => {
+                let (A, B) = (wire(xref), wire(yref));
+                let (C, g) = operations::garble_yao(A, B, q, tt, r, &self.deltas, &self.cipher);
+                if self.checked {
+                    let gate = g.as_ref().unwrap();
+                    operations::check_yao(A, B, q, tt, r, &self.deltas, &self.cipher, &C, gate);
+                }
+                (C, g)
+            }
+
+            Gate::HalfGate { xref, yref, .. } =>
+                operations::garble_half_gate(wire(xref), wire(yref), r, &self.deltas, &self.cipher, &mut self.rng),
+        }
+    }
+
+    /// The zero wires of the labeled outputs, in order. Fails if called before all wires
+    /// have been generated using the iterator interface.
+    pub fn labeled_outputs(&self) -> Result<Vec<Wire>, failure::Error> {
+        if self.current_wire < self.circuit.gates.len() {
+            return Err(failure::err_msg("Garbler::labeled_outputs called before all wires were generated"));
+        }
+        Ok(self.circuit.output_refs.iter().enumerate()
+            .filter(|&(i,_)| self.output_mode(i) == OutputMode::Labeled)
+            .map(|(_,&r)| self.wires[r].clone())
+            .collect())
+    }
+
     /// Extract an `Encoder` from the `Garbler`.
     pub fn encoder(&self) -> Encoder {
         Encoder::new(self.inputs.clone(), self.deltas.clone())
@@ -104,13 +480,17 @@ impl <'a> Garbler<'a> {
             return Err(failure::err_msg("Garbler::decoder called before all wires were generated"));
         }
         let outs = self.circuit.output_refs.iter().enumerate().map(|(i, &r)| {
-            operations::garble_output(&self.wires[r], i, &self.deltas)
+            match self.circuit.output_mode(i) {
+                OutputMode::Decoded => operations::garble_output(&self.wires[r], i, &self.deltas, &self.cipher),
+                OutputMode::Labeled => Vec::new(),
+            }
         }).collect();
-        Ok(Decoder::new(outs))
+        let mods = self.circuit.output_moduli();
+        Ok(Decoder::new(outs, mods).with_output_names(self.circuit.output_names.clone()))
     }
 }
 
-impl <'a> Iterator for Garbler<'a> {
+impl <'a, R: RngCore> Iterator for Garbler<'a, R> {
     type Item = GarbledGate;
 
     fn next(&mut self) -> Option<GarbledGate> {
@@ -125,25 +505,9 @@ impl <'a> Iterator for Garbler<'a> {
                 return None;
             }
 
-            let q = self.circuit.modulus(self.current_wire);
-
-            let (w,g) = match self.circuit.gates[self.current_wire] {
-                Gate::Input { id } => (self.inputs[id].clone(), None),
-                Gate::Const { id } => (self.consts[id].clone(), None),
-
-                Gate::Add { xref, yref } => (self.wires[xref].plus(&self.wires[yref]),  None),
-                Gate::Sub { xref, yref } => (self.wires[xref].minus(&self.wires[yref]), None),
-                Gate::Cmul { xref, c }   => (self.wires[xref].cmul(c),                  None),
-
-                Gate::Proj { xref, ref tt, .. } =>
-                    operations::garble_projection(&self.wires[xref], q, tt, self.current_wire, &self.deltas),
-
-                Gate::Yao { xref, yref, ref tt, .. } =>
-                    operations::garble_yao(&self.wires[xref], &self.wires[yref], q, tt, self.current_wire, &self.deltas),
-
-                Gate::HalfGate { xref, yref, .. } =>
-                    operations::garble_half_gate(&self.wires[xref], &self.wires[yref], self.current_wire, &self.deltas, &mut self.rng),
-            };
+            let wires = std::mem::take(&mut self.wires);
+            let (w,g) = self.garble_gate(self.current_wire, |x| &wires[x]);
+            self.wires = wires;
 
             self.wires.push(w);
             gate = g;
@@ -176,6 +540,24 @@ impl Encoder {
         }).collect()
     }
 
+    /// Like `encode`, but tags each wire with its input index and modulus. Fails if there
+    /// is the wrong number of inputs or one of them is out of range for its modulus.
+    pub fn encode_labeled(&self, inputs: &[u16]) -> Result<EncodedInputs, failure::Error> {
+        if inputs.len() != self.inputs.len() {
+            return Err(failure::format_err!("[encoder.encode_labeled] expected {} inputs but got {}",
+                                            self.inputs.len(), inputs.len()));
+        }
+        let inputs = inputs.iter().enumerate().map(|(id, &x)| {
+            let modulus = self.inputs[id].modulus();
+            if x >= modulus {
+                return Err(failure::format_err!("[encoder.encode_labeled] input {} is out of range for modulus {}",
+                                                id, modulus));
+            }
+            Ok(EncodedInput { id, modulus, wire: self.encode_input(x, id) })
+        }).collect::<Result<_,_>>()?;
+        Ok(EncodedInputs { inputs })
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         bincode::serialize(self).expect("couldn't serialize Encoder")
     }
@@ -186,35 +568,194 @@ impl Encoder {
     }
 }
 
+impl EncodedInputs {
+    /// Check that there is one wire for each input of `c`, in order, each with the
+    /// modulus of that input.
+    pub fn verify_against(&self, c: &Circuit) -> Result<(), failure::Error> {
+        if self.inputs.len() != c.ninputs() {
+            return Err(failure::format_err!("[encoded_inputs.verify_against] expected {} inputs but got {}",
+                                            c.ninputs(), self.inputs.len()));
+        }
+        for (i, x) in self.inputs.iter().enumerate() {
+            if x.id != i {
+                return Err(failure::format_err!("[encoded_inputs.verify_against] input {} has id {}", i, x.id));
+            }
+            let q = c.input_mod(i);
+            if x.modulus != q || x.wire.modulus() != q {
+                return Err(failure::format_err!("[encoded_inputs.verify_against] input {} should have modulus {} but has {}",
+                                                i, q, if x.modulus != q { x.modulus } else { x.wire.modulus() }));
+            }
+        }
+        Ok(())
+    }
+
+    /// The wires, in order, for passing to `Evaluator::eval`.
+    pub fn wires(&self) -> Vec<Wire> {
+        self.inputs.iter().map(|x| x.wire.clone()).collect()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("couldn't serialize EncodedInputs")
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, failure::Error> {
+        bincode::deserialize(bs)
+            .map_err(|_| failure::err_msg("error decoding EncodedInputs from bytes"))
+    }
+}
+
 impl Decoder {
-    pub fn new(outputs: Vec<Vec<u128>>) -> Self {
-        Decoder { outputs }
+    pub fn new(outputs: Vec<Vec<u128>>, output_moduli: Vec<u16>) -> Self {
+        debug_assert_eq!(outputs.len(), output_moduli.len());
+        Decoder { outputs, output_moduli, output_names: HashMap::new() }
+    }
+
+    /// Name outputs for `decode_named`, mapping each name to an output index.
+    pub fn with_output_names(mut self, names: HashMap<String, usize>) -> Self {
+        self.output_names = names;
+        self
     }
 
+    pub fn output_moduli(&self) -> &[u16] {
+        &self.output_moduli
+    }
+
+    /// Decode the output wires. Panics if `try_decode` would fail.
     pub fn decode(&self, ws: &[Wire]) -> Vec<u16> {
-        debug_assert_eq!(ws.len(), self.outputs.len());
-        let mut outs = Vec::new();
+        self.try_decode(ws).unwrap()
+    }
+
+    /// Decode the output wires, failing if there is the wrong number of them, one of them
+    /// has an unexpected modulus, or one of them does not decode to any value. Labeled
+    /// outputs are skipped, so the result holds only the values of decoded outputs.
+    pub fn try_decode(&self, ws: &[Wire]) -> Result<Vec<u16>, failure::Error> {
+        self.try_decode_with_cipher(ws, &AES)
+    }
+
+    /// Like `try_decode`, using `cipher` for hashing, which must match the garbler's.
+    pub fn try_decode_with_cipher(&self, ws: &[Wire], cipher: &dyn Cipher) -> Result<Vec<u16>, failure::Error> {
+        if ws.len() != self.outputs.len() {
+            return Err(failure::format_err!("[decoder.decode] expected {} outputs but got {}",
+                                            self.outputs.len(), ws.len()));
+        }
+        let mut outs = Vec::with_capacity(ws.len());
         for i in 0..ws.len() {
-            let q = ws[i].modulus();
-            for k in 0..q {
-                let h = ws[i].hash(operations::output_tweak(i,k));
-                if h == self.outputs[i][k as usize] {
-                    outs.push(k);
-                    break;
-                }
+            if !self.outputs[i].is_empty() {
+                outs.push(self.decode_one_with_cipher(i, &ws[i], cipher)?);
             }
         }
-        debug_assert_eq!(ws.len(), outs.len(), "decoding failed");
-        outs
+        Ok(outs)
+    }
+
+    /// Every value each decoded output wire matches, rather than just the first. There
+    /// should always be exactly one, so this is for detecting ambiguous decodings in tests,
+    /// for instance with tiny moduli. Fails if there is the wrong number of wires, or if
+    /// one has the wrong modulus.
+    pub fn decode_all_matches(&self, ws: &[Wire]) -> Result<Vec<Vec<u16>>, failure::Error> {
+        self.decode_all_matches_with_cipher(ws, &AES)
+    }
+
+    /// Like `decode_all_matches`, using `cipher` for hashing, which must match the garbler's.
+    pub fn decode_all_matches_with_cipher(&self, ws: &[Wire], cipher: &dyn Cipher)
+        -> Result<Vec<Vec<u16>>, failure::Error>
+    {
+        if ws.len() != self.outputs.len() {
+            return Err(failure::format_err!("[decoder.decode_all_matches] expected {} outputs but got {}",
+                                            self.outputs.len(), ws.len()));
+        }
+        (0..ws.len()).filter(|&i| !self.outputs[i].is_empty()).map(|i| {
+            let q = self.output_moduli[i];
+            if ws[i].modulus() != q {
+                return Err(failure::format_err!("[decoder.decode_all_matches] output {} should have modulus {} but has {}",
+                                                i, q, ws[i].modulus()));
+            }
+            Ok((0..q).filter(|&k| {
+                ws[i].hash_with(cipher, operations::output_tweak(i,k)) == self.outputs[i][k as usize]
+            }).collect())
+        }).collect()
+    }
+
+    /// Decode the output wires into one integer for each bundle in `layout`. The bundles
+    /// may be listed in any order, but must exactly tile the outputs, none of which may be
+    /// labeled. Fails as `decode_one` does, or if the layout is bad.
+    pub fn decode_structured(&self, ws: &[Wire], layout: &[OutputBundle]) -> Result<Vec<u128>, failure::Error> {
+        if ws.len() != self.outputs.len() {
+            return Err(failure::format_err!("[decoder.decode_structured] expected {} outputs but got {}",
+                                            self.outputs.len(), ws.len()));
+        }
+        let mut pos = 0;
+        for b in layout.iter().sorted_by_key(|b| b.start) {
+            if b.start != pos || b.len == 0 {
+                return Err(failure::format_err!("[decoder.decode_structured] layout does not tile the outputs at {}", pos));
+            }
+            pos += b.len;
+        }
+        if pos != ws.len() {
+            return Err(failure::format_err!("[decoder.decode_structured] layout covers {} of {} outputs", pos, ws.len()));
+        }
+
+        layout.iter().map(|b| {
+            let range = b.start .. b.start + b.len;
+            let ds = range.clone().map(|i| self.decode_one(i, &ws[i])).collect::<Result<Vec<_>,_>>()?;
+            let ms = &self.output_moduli[range];
+            Ok(match b.base {
+                OutputBase::MixedRadix => numbers::from_mixed_radix(&ds, ms),
+                OutputBase::Crt => numbers::crt_inv(ms, &ds),
+            })
+        }).collect()
+    }
+
+    /// Decode the outputs named with `Builder::output_named`, keyed by name. Unnamed outputs
+    /// are ignored. Fails if there is the wrong number of wires, or as `decode_one` does
+    /// for each named output.
+    pub fn decode_named(&self, ws: &[Wire]) -> Result<HashMap<String, u16>, failure::Error> {
+        if ws.len() != self.outputs.len() {
+            return Err(failure::format_err!("[decoder.decode_named] expected {} outputs but got {}",
+                                            self.outputs.len(), ws.len()));
+        }
+        self.output_names.iter().map(|(name, &i)| {
+            Ok((name.clone(), self.decode_one(i, &ws[i])?))
+        }).collect()
+    }
+
+    /// Decode the single output wire `w` of output `i`, for instance as soon as it has been
+    /// evaluated. Fails if `i` is out of range or a labeled output, or as `try_decode` does.
+    pub fn decode_one(&self, i: usize, w: &Wire) -> Result<u16, failure::Error> {
+        self.decode_one_with_cipher(i, w, &AES)
+    }
+
+    fn decode_one_with_cipher(&self, i: usize, w: &Wire, cipher: &dyn Cipher) -> Result<u16, failure::Error> {
+        if i >= self.outputs.len() {
+            return Err(failure::format_err!("[decoder.decode] no output {}", i));
+        }
+        if self.outputs[i].is_empty() {
+            return Err(failure::format_err!("[decoder.decode] output {} is labeled", i));
+        }
+        let q = self.output_moduli[i];
+        if w.modulus() != q {
+            return Err(failure::format_err!("[decoder.decode] output {} should have modulus {} but has {}",
+                                            i, q, w.modulus()));
+        }
+        (0..q).find(|&k| {
+            w.hash_with(cipher, operations::output_tweak(i,k)) == self.outputs[i][k as usize]
+        }).ok_or_else(|| failure::format_err!("[decoder.decode] output {} failed to decode", i))
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         bincode::serialize(self).expect("couldn't serialize Decoder")
     }
 
+    /// Deserialize a `Decoder`, also accepting the bytes of a `Decoder` from before output
+    /// moduli and names were stored, which held only the tables. Trailing bytes are an error.
     pub fn from_bytes(bs: &[u8]) -> Result<Self, failure::Error> {
-        bincode::deserialize(bs)
-            .map_err(|_| failure::err_msg("error decoding Decoder from bytes"))
+        let opts = || bincode::DefaultOptions::new().with_fixint_encoding().reject_trailing_bytes();
+        opts().deserialize(bs).or_else(|_| {
+            // the old format has a table entry for every value of each output
+            opts().deserialize::<Vec<Vec<u128>>>(bs).map(|outputs| {
+                let mods = outputs.iter().map(|t| t.len() as u16).collect();
+                Decoder::new(outputs, mods)
+            })
+        }).map_err(|_| failure::err_msg("error decoding Decoder from bytes"))
     }
 }
 
@@ -231,7 +772,97 @@ impl Evaluator {
         c
     }
 
+    /// Break `size` down by the output modulus of the gate each ciphertext belongs to, with
+    /// each const wire counted under its own modulus. The circuit must be the one garbled.
+    pub fn size_by_modulus(&self, c: &Circuit) -> HashMap<u16, usize> {
+        let mut sizes = HashMap::new();
+        for (r, g) in c.gates.iter().enumerate() {
+            let n = match *g {
+                Gate::Proj { id, .. } | Gate::Yao { id, .. } | Gate::HalfGate { id, .. } => self.gates[id].len(),
+                Gate::Const { .. } => 1,
+                _ => continue,
+            };
+            *sizes.entry(c.modulus(r)).or_insert(0) += n;
+        }
+        sizes
+    }
+
     pub fn eval(&self, c: &Circuit, inputs: &[Wire]) -> Vec<Wire> {
+        self.eval_with_cipher(c, inputs, &AES)
+    }
+
+    /// The ciphertexts of the garbled gate with garbling id `id`, if there is one.
+    pub fn gate(&self, id: usize) -> Option<&GarbledGate> {
+        self.gates.get(id)
+    }
+
+    pub fn num_gates(&self) -> usize {
+        self.gates.len()
+    }
+
+    /// Evaluate on input labels which have each been shifted by adding the corresponding
+    /// public wire in `input_offsets`, removing the offsets first.
+    pub fn eval_with_offsets(&self, c: &Circuit, inputs: &[Wire], input_offsets: &[Wire]) -> Vec<Wire> {
+        assert_eq!(inputs.len(), input_offsets.len(), "[evaluator.eval_with_offsets] need one offset per input");
+        let xs = inputs.iter().zip(input_offsets.iter()).enumerate().map(|(i, (x, o))| {
+            assert_eq!(x.modulus(), o.modulus(), "[evaluator.eval_with_offsets] offset {} has the wrong modulus", i);
+            x.minus(o)
+        }).collect_vec();
+        self.eval(c, &xs)
+    }
+
+    /// Evaluate, then decode the outputs with `de`, for when the evaluator has been given
+    /// the decoder. Fails as `Decoder::try_decode` does.
+    pub fn eval_and_decode(&self, c: &Circuit, inputs: &[Wire], de: &Decoder) -> Result<Vec<u16>, failure::Error> {
+        de.try_decode(&self.eval(c, inputs))
+    }
+
+    /// Check that this garbling has the shape expected for `c`: one garbled gate of the
+    /// right size for each non-free gate, and one wire for each constant.
+    pub fn is_consistent_with(&self, c: &Circuit) -> Result<(), ConsistencyError> {
+        if self.gates.len() != c.num_nonfree_gates {
+            return Err(ConsistencyError::GateCount { expected: c.num_nonfree_gates, got: self.gates.len() });
+        }
+        for g in c.gates.iter() {
+            let id = match *g {
+                Gate::Proj { id, .. } | Gate::Yao { id, .. } | Gate::HalfGate { id, .. } => id,
+                _ => continue,
+            };
+            let expected = c.ciphertexts(g);
+            let got = self.gates.get(id).map_or(0, |gate| gate.len());
+            if got != expected {
+                return Err(ConsistencyError::GateSize { id, expected, got });
+            }
+        }
+        if self.consts.len() != c.const_refs.len() {
+            return Err(ConsistencyError::ConstCount { expected: c.const_refs.len(), got: self.consts.len() });
+        }
+        Ok(())
+    }
+
+    /// Evaluate using `cipher` for hashing, which must match the garbler's.
+    pub fn eval_with_cipher(&self, c: &Circuit, inputs: &[Wire], cipher: &dyn Cipher) -> Vec<Wire> {
+        self.eval_core(c, inputs, cipher, |id| Cow::Borrowed(&self.gates[id]))
+    }
+
+    /// Evaluate a circuit garbled by `garble_with_tweak_base` with the same `tweak_base`.
+    pub fn eval_with_tweak_base(&self, c: &Circuit, inputs: &[Wire], tweak_base: u128) -> Vec<Wire> {
+        self.eval_with_cipher(c, inputs, &TweakOffset::new(tweak_base))
+    }
+
+    /// Evaluate, requesting the ciphertexts of each garbled gate by id from `gate_provider`
+    /// rather than using the gates stored in the `Evaluator`. Each id is requested exactly
+    /// once, in circuit order, so the caller may supply gates regardless of the order in
+    /// which they arrived.
+    pub fn eval_with_provider<F>(&self, c: &Circuit, inputs: &[Wire], mut gate_provider: F) -> Vec<Wire>
+        where F: FnMut(Id) -> GarbledGate
+    {
+        self.eval_core(c, inputs, &AES, |id| Cow::Owned(gate_provider(id)))
+    }
+
+    fn eval_core<'b, F>(&self, c: &Circuit, inputs: &[Wire], cipher: &dyn Cipher, mut get_gate: F) -> Vec<Wire>
+        where F: FnMut(Id) -> Cow<'b, GarbledGate>
+    {
         let mut wires: Vec<Wire> = Vec::new();
         for i in 0..c.gates.len() {
             let q = c.modulus(i);
@@ -241,56 +872,51 @@ impl Evaluator {
                 Gate::Const { id, .. }   => self.consts[id].clone(),
                 Gate::Add { xref, yref } => wires[xref].plus(&wires[yref]),
                 Gate::Sub { xref, yref } => wires[xref].minus(&wires[yref]),
-                Gate::Cmul { xref, c }   => wires[xref].cmul(c),
+                Gate::Cmul { xref, c }   => wires[xref].cmul((c % q as u32) as u16),
 
                 Gate::Proj { xref, id, .. } => {
+                    let gate = get_gate(id);
                     let x = &wires[xref];
                     if x.color() == 0 {
-                        x.hashback(i as u128, q)
+                        x.hashback_with(cipher, i as u128, q)
                     } else {
-                        let ct = self.gates[id][x.color() as usize - 1];
-                        Wire::from_u128(ct ^ x.hash(i as u128), q)
+                        let ct = gate[x.color() as usize - 1];
+                        Wire::from_u128(ct ^ x.hash_with(cipher, i as u128), q)
                     }
                 }
 
                 Gate::Yao { xref, yref, id, .. } => {
-                    let a = &wires[xref];
-                    let b = &wires[yref];
-                    if a.color() == 0 && b.color() == 0 {
-                        a.hashback2(&b, operations::tweak(i), q)
-                    } else {
-                        let ix = a.color() as usize * c.modulus(yref) as usize + b.color() as usize;
-                        let ct = self.gates[id][ix - 1];
-                        Wire::from_u128(ct ^ a.hash2(&b, operations::tweak(i)), q)
-                    }
+                    let gate = get_gate(id);
+                    operations::eval_yao(&wires[xref], &wires[yref], q, i, &gate, cipher)
                 }
 
                 Gate::HalfGate { xref, yref, id } => {
+                    let gate = get_gate(id);
                     let g = operations::tweak2(i as u64, 0);
 
                     // garbler's half gate
                     let A = &wires[xref];
                     let L = if A.color() == 0 {
-                        A.hashback(g,q)
+                        A.hashback_with(cipher, g, q)
                     } else {
-                        let ct_left = self.gates[id][A.color() as usize - 1];
-                        Wire::from_u128(ct_left ^ A.hash(g), q)
+                        let ct_left = gate[A.color() as usize - 1];
+                        Wire::from_u128(ct_left ^ A.hash_with(cipher, g), q)
                     };
 
                     // evaluator's half gate
                     let B = &wires[yref];
                     let R = if B.color() == 0 {
-                        B.hashback(g,q)
+                        B.hashback_with(cipher, g, q)
                     } else {
-                        let ct_right = self.gates[id][(q + B.color()) as usize - 2];
-                        Wire::from_u128(ct_right ^ B.hash(g), q)
+                        let ct_right = gate[(q + B.color()) as usize - 2];
+                        Wire::from_u128(ct_right ^ B.hash_with(cipher, g), q)
                     };
 
                     // hack for unequal mods
                     let new_b_color = if c.modulus(xref) != c.modulus(yref) {
-                        let minitable = *self.gates[id].last().unwrap();
+                        let minitable = *gate.last().unwrap();
                         let ct = minitable >> (B.color() * 16);
-                        let pt = B.hash(operations::tweak2(i as u64, 1)) ^ ct;
+                        let pt = B.hash_with(cipher, operations::tweak2(i as u64, 1)) ^ ct;
                         pt as u16
                     } else {
                         B.color()
@@ -311,6 +937,66 @@ impl Evaluator {
         bincode::serialize(self).expect("couldn't serialize Evaluator")
     }
 
+    /// The ciphertexts of every gate followed by the const wires, each as 16 little-endian
+    /// bytes with no framing. The circuit determines how to split them up again, see
+    /// `from_ciphertext_bytes`.
+    pub fn ciphertext_bytes(&self) -> Vec<u8> {
+        let mut bs = Vec::with_capacity(16 * self.size());
+        for ct in self.gates.iter().flatten() {
+            bs.extend_from_slice(&ct.to_le_bytes());
+        }
+        for w in self.consts.iter() {
+            bs.extend_from_slice(&w.as_u128().to_le_bytes());
+        }
+        bs
+    }
+
+    /// The byte range of every garbled gate in the output of `ciphertext_bytes`, in order,
+    /// followed by one entry per const wire. The ranges are contiguous and cover the whole
+    /// buffer, so single gates can be read from a file or transferred on their own. The
+    /// circuit must be the one garbled.
+    pub fn layout(&self, c: &Circuit) -> Vec<GateLayout> {
+        let mut kinds = c.gates.iter().filter_map(|g| match *g {
+            Gate::Proj { id, .. } => Some((id, GateKind::Proj)),
+            Gate::Yao { id, .. } => Some((id, GateKind::Yao)),
+            Gate::HalfGate { id, .. } => Some((id, GateKind::HalfGate)),
+            _ => None,
+        }).collect_vec();
+        kinds.sort_by_key(|&(id, _)| id);
+        let consts = (0..self.consts.len()).map(|id| (id, GateKind::Const));
+        let lens = self.gates.iter().map(Vec::len).chain(self.consts.iter().map(|_| 1));
+
+        let mut offset = 0;
+        kinds.into_iter().chain(consts).zip(lens).map(|((id, kind), n)| {
+            let l = GateLayout { id, kind, offset, len: 16 * n };
+            offset += l.len;
+            l
+        }).collect()
+    }
+
+    /// Split the output of `ciphertext_bytes` back into gates and const wires, using the
+    /// sizes of the gates and moduli of the consts in `c`.
+    pub fn from_ciphertext_bytes(c: &Circuit, bs: &[u8]) -> Result<Self, failure::Error> {
+        let mut sizes = c.gates.iter().filter_map(|g| match *g {
+            Gate::Proj { id, .. } | Gate::Yao { id, .. } | Gate::HalfGate { id, .. } => Some((id, c.ciphertexts(g))),
+            _ => None,
+        }).collect_vec();
+        sizes.sort();
+        let nblocks = sizes.iter().map(|&(_, n)| n).sum::<usize>() + c.const_refs.len();
+        if bs.len() != 16 * nblocks {
+            return Err(failure::format_err!("[evaluator.from_ciphertext_bytes] expected {} bytes but got {}",
+                                            16 * nblocks, bs.len()));
+        }
+        let mut blocks = bs.chunks(16).map(|b| {
+            let mut block = [0; 16];
+            block.copy_from_slice(b);
+            u128::from_le_bytes(block)
+        });
+        let gates = sizes.iter().map(|&(_, n)| blocks.by_ref().take(n).collect()).collect();
+        let consts = c.const_refs.iter().zip(blocks).map(|(&r, x)| Wire::from_u128(x, c.modulus(r))).collect();
+        Ok(Evaluator::new(gates, consts))
+    }
+
     pub fn from_bytes(bs: &[u8]) -> Result<Self, failure::Error> {
         bincode::deserialize(bs)
             .map_err(|_| failure::err_msg("error decoding Evaluator from bytes"))
@@ -321,10 +1007,12 @@ impl Evaluator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::circuit::{Circuit, Builder};
+    use crate::aes::CountingCipher;
+    use crate::circuit::{Circuit, Builder, FoldOp};
     use crate::numbers;
     use crate::util::RngExt;
-    use rand::thread_rng;
+    use rand::{thread_rng, SeedableRng};
+    use rand::rngs::StdRng;
     use itertools::Itertools;
 
     // helper {{{
@@ -396,159 +1084,1774 @@ mod tests {
         });
     }
 //}}}
-    #[test] // sub {{{
-    fn sub() {
-        garble_test_helper(|q| {
-            let mut b = Builder::new();
-            let x = b.input(q);
-            let y = b.input(q);
-            let z = b.sub(x,y);
-            b.output(z);
-            b.finish()
-        });
+    #[test] // bitwise {{{
+    fn bitwise() {
+        let mut rng = thread_rng();
+        let n = 1 + rng.gen_usize() % 64;
+        let mut b = Builder::new();
+        let xs = b.inputs(n,2);
+        let ys = b.inputs(n,2);
+        let zs = b.bitwise_and(&xs, &ys);
+        b.outputs(&zs);
+        let zs = b.bitwise_or(&xs, &ys);
+        b.outputs(&zs);
+        let zs = b.bitwise_xor(&xs, &ys);
+        b.outputs(&zs);
+        let c = b.finish();
+
+        let (en, de, ev) = garble(&c);
+        for _ in 0..16 {
+            let x = rng.gen_u128();
+            let y = rng.gen_u128();
+            let mut bits = numbers::u128_to_bits(x,n);
+            bits.extend(numbers::u128_to_bits(y,n));
+            let mut should_be = numbers::u128_to_bits(x & y, n);
+            should_be.extend(numbers::u128_to_bits(x | y, n));
+            should_be.extend(numbers::u128_to_bits(x ^ y, n));
+            assert_eq!(c.eval(&bits), should_be);
+            assert_eq!(de.decode(&ev.eval(&c, &en.encode(&bits))), should_be);
+        }
     }
 //}}}
-    #[test] // cmul {{{
-    fn cmul() {
-        garble_test_helper(|q| {
+    #[test] // cond_swap {{{
+    fn cond_swap() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = rng.gen_modulus();
+            let n = 1 + rng.gen_usize() % 8;
             let mut b = Builder::new();
-            let x = b.input(q);
-            let _ = b.input(q);
-            let z;
-            if q > 2 {
-                z = b.cmul(x, 2);
-            } else {
-                z = b.cmul(x, 1);
+            let sel = b.input(2);
+            let xs = b.inputs(n,q);
+            let ys = b.inputs(n,q);
+            let (zs, ws) = b.cond_swap(sel, &xs, &ys);
+            b.outputs(&zs);
+            b.outputs(&ws);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for s in 0..2 {
+                let xs = (0..n).map(|_| rng.gen_u16() % q).collect_vec();
+                let ys = (0..n).map(|_| rng.gen_u16() % q).collect_vec();
+                let mut inps = vec![s];
+                inps.extend(&xs);
+                inps.extend(&ys);
+                let should_be = if s == 0 { [xs,ys].concat() } else { [ys,xs].concat() };
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
             }
-            b.output(z);
-            b.finish()
-        });
+        }
     }
 //}}}
-    #[test] // proj_cycle {{{
-    fn proj_cycle() {
-        garble_test_helper(|q| {
-            let mut tab = Vec::new();
-            for i in 0..q {
-                tab.push((i + 1) % q);
-            }
+    #[test] // argmax {{{
+    fn argmax() {
+        let mut rng = thread_rng();
+        for nbundles in 2..10 {
+            let q = 2 + rng.gen_u16() % 7;
+            let n = 1 + rng.gen_usize() % 3;
+            let Q = (q as u128).pow(n as u32);
+
             let mut b = Builder::new();
-            let x = b.input(q);
-            let _ = b.input(q);
-            let z = b.proj(x, q, tab);
+            let xs = (0..nbundles).map(|_| b.inputs(n,q)).collect_vec();
+            let z = b.argmax(&xs);
             b.output(z);
-            b.finish()
-        });
-    }
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                // a small range of values, so that ties are common
+                let vals = (0..nbundles).map(|_| rng.gen_u128() % Q.min(4)).collect_vec();
+                let inps = vals.iter().flat_map(|&x| numbers::as_base_q(x,q,n)).collect_vec();
+                let max = *vals.iter().max().unwrap();
+                let should_be = vec![vals.iter().position(|&x| x == max).unwrap() as u16];
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
 //}}}
-    #[test] // proj_rand {{{
-    fn proj_rand() {
+    #[test] // argmin_with_value {{{
+    fn argmin_with_value() {
+        let mut rng = thread_rng();
+        for nbundles in 2..10 {
+            let q = 2 + rng.gen_u16() % 7;
+            let n = 1 + rng.gen_usize() % 3;
+            let Q = (q as u128).pow(n as u32);
+
+            let mut b = Builder::new();
+            let xs = (0..nbundles).map(|_| b.inputs(n,q)).collect_vec();
+            let (z, min) = b.argmin_with_value(&xs);
+            b.output(z);
+            b.outputs(&min);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for i in 0..8 {
+                // a small range of values in half the cases, so that ties are common
+                let range = if i % 2 == 0 { Q.min(4) } else { Q };
+                let vals = (0..nbundles).map(|_| rng.gen_u128() % range).collect_vec();
+                let inps = vals.iter().flat_map(|&x| numbers::as_base_q(x,q,n)).collect_vec();
+                let min = *vals.iter().min().unwrap();
+                let mut should_be = vec![vals.iter().position(|&x| x == min).unwrap() as u16];
+                should_be.extend(numbers::as_base_q(min,q,n));
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // sort {{{
+    fn sort() {
+        let mut rng = thread_rng();
+        for nbundles in 0..10 {
+            let q = 2 + rng.gen_u16() % 7;
+            let n = 1 + rng.gen_usize() % 3;
+            let Q = (q as u128).pow(n as u32);
+
+            let mut b = Builder::new();
+            let xs = (0..nbundles).map(|_| b.inputs(n,q)).collect_vec();
+            let zs = b.sort(&xs);
+            for z in zs.iter() {
+                b.outputs(z);
+            }
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..4 {
+                let mut vals = (0..nbundles).map(|_| rng.gen_u128() % Q).collect_vec();
+                let inps = vals.iter().flat_map(|&x| numbers::as_base_q(x,q,n)).collect_vec();
+                vals.sort();
+                let should_be = vals.iter().flat_map(|&x| numbers::as_base_q(x,q,n)).collect_vec();
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // median {{{
+    fn median() {
+        let mut rng = thread_rng();
+        for nbundles in 1..12 {
+            let q = 2 + rng.gen_u16() % 7;
+            let n = 1 + rng.gen_usize() % 3;
+            let Q = (q as u128).pow(n as u32);
+
+            let mut b = Builder::new();
+            let xs = (0..nbundles).map(|_| b.inputs(n,q)).collect_vec();
+            let zs = b.median(&xs);
+            b.outputs(&zs);
+            let c = b.finish();
+
+            let mut b = Builder::new();
+            let xs = (0..nbundles).map(|_| b.inputs(n,q)).collect_vec();
+            let zs = b.sort(&xs);
+            b.outputs(&zs[(nbundles-1) / 2]);
+            assert!(c.num_nonfree_gates <= b.finish().num_nonfree_gates);
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..4 {
+                let mut vals = (0..nbundles).map(|_| rng.gen_u128() % Q).collect_vec();
+                let inps = vals.iter().flat_map(|&x| numbers::as_base_q(x,q,n)).collect_vec();
+                vals.sort();
+                let should_be = numbers::as_base_q(vals[(nbundles-1) / 2],q,n);
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // dot {{{
+    fn dot() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let xs = b.inputs(8, q);
+            let ys = b.inputs(8, q);
+            let z = b.dot(&xs, &ys, q);
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // sum_mod_many {{{
+    fn sum_mod_many() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let p = rng.gen_prime();
+            let n = 1 + rng.gen_usize() % 32;
+            let ks = (0..n).map(|_| rng.gen_u16() % p).collect_vec();
+            let mut b = Builder::new();
+            let xs = b.inputs(n,p);
+            let terms = xs.iter().zip(ks.iter()).map(|(&x,&k)| b.cmul(x,k)).collect_vec();
+            let z = b.sum_mod_many(&terms, p);
+            b.output(z);
+            let c = b.finish();
+            assert_eq!(c.num_nonfree_gates, 1);
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                let inps = (0..n).map(|_| rng.gen_u16() % p).collect_vec();
+                let sum = inps.iter().zip(ks.iter()).map(|(&x,&k)| x as u128 * k as u128).sum::<u128>();
+                let should_be = vec![(sum % p as u128) as u16];
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // weighted_sum {{{
+    fn weighted_sum() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let p = rng.gen_modulus();
+            let n = 1 + rng.gen_usize() % 32;
+            let ws = (0..n).map(|_| rng.gen_u16()).collect_vec();
+            let mut b = Builder::new();
+            let xs = b.inputs(n,p);
+            let z = b.weighted_sum(&xs, &ws, p);
+            b.output(z);
+            let c = b.finish();
+            assert_eq!(c.num_nonfree_gates, 0);
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                let inps = (0..n).map(|_| rng.gen_u16() % p).collect_vec();
+                let sum = inps.iter().zip(ws.iter()).map(|(&x,&w)| x as u128 * w as u128).sum::<u128>();
+                let should_be = vec![(sum % p as u128) as u16];
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // horner {{{
+    fn horner() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let p = rng.gen_prime();
+            let n = 1 + rng.gen_usize() % 8;
+            let mut b = Builder::new();
+            let coeffs = b.inputs(n,p);
+            let x = b.input(p);
+            let z = b.horner(&coeffs, x, p);
+            b.output(z);
+            let c = b.finish();
+            assert_eq!(c.num_nonfree_gates, n - 1);
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                let inps = (0..=n).map(|_| rng.gen_u16() % p).collect_vec();
+                let x = inps[n] as u32;
+                let y = inps[..n].iter().fold(0, |acc, &c| (acc * x + c as u32) % p as u32);
+                let should_be = vec![y as u16];
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // ema_update {{{
+    fn ema_update() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let p = 2 + rng.gen_u16() % 60;
+            let den = 1 + rng.gen_u16() % 10;
+            let num = 1 + rng.gen_u16() % den;
+            let n = 1 + rng.gen_usize() % 3;
+            let k = 1 + rng.gen_usize() % 6;
+
+            let mut b = Builder::new();
+            let mut acc = b.inputs(n,p);
+            for _ in 0..k {
+                let sample = b.inputs(n,p);
+                acc = b.ema_update(&acc, &sample, num, den, p);
+            }
+            b.outputs(&acc);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            let alpha = num as f64 / den as f64;
+            for _ in 0..4 {
+                let inps = (0..n * (k+1)).map(|_| rng.gen_u16() % p).collect_vec();
+                let mut rounded = inps[..n].to_vec();
+                let mut exact = rounded.iter().map(|&x| x as f64).collect_vec();
+                for step in inps[n..].chunks(n) {
+                    for i in 0..n {
+                        rounded[i] = ((num as u32 * step[i] as u32 + (den - num) as u32 * rounded[i] as u32)
+                                      / den as u32) as u16;
+                        exact[i] = alpha * step[i] as f64 + (1.0 - alpha) * exact[i];
+                    }
+                }
+                for i in 0..n {
+                    assert!(rounded[i] as f64 <= exact[i] + 1e-9);
+                    assert!(exact[i] - (rounded[i] as f64) < 1.0 / alpha);
+                }
+                assert_eq!(c.eval(&inps), rounded);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), rounded);
+            }
+        }
+    }
+//}}}
+    #[test] // pow_secret {{{
+    fn pow_secret() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let p = rng.gen_prime();
+            let m = 2 + rng.gen_u16() % 15;
+            let mut b = Builder::new();
+            let x = b.input(p);
+            let e = b.input(m);
+            let z = b.pow_secret(x, e, p);
+            b.output(z);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                let x = rng.gen_u16() % p;
+                let e = rng.gen_u16() % m;
+                let should_be = vec![(0..e).fold(1, |acc, _| acc * x as u32 % p as u32) as u16];
+                assert_eq!(c.eval(&[x,e]), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&[x,e]))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // secret_lookup {{{
+    fn secret_lookup() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let n = 2 + rng.gen_u16() % 15;
+            let q = rng.gen_modulus();
+            let mut b = Builder::new();
+            let ix = b.input(n);
+            let table = b.inputs(n as usize, q);
+            let z = b.secret_lookup(ix, &table);
+            b.output(z);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                let i = rng.gen_u16() % n;
+                let tab = (0..n).map(|_| rng.gen_u16() % q).collect_vec();
+                let mut inps = vec![i];
+                inps.extend(&tab);
+                let should_be = vec![tab[i as usize]];
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // at_least_k {{{
+    fn at_least_k() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let n = 1 + rng.gen_usize() % 16;
+            let ks = [0, 1, n / 2, n, n + 1];
+            let mut b = Builder::new();
+            let xs = b.inputs(n,2);
+            let p = b.popcount(&xs);
+            b.output(p);
+            for &k in ks.iter() {
+                let z = b.at_least_k(&xs, k);
+                b.output(z);
+            }
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                let inps = (0..n).map(|_| rng.gen_u16() % 2).collect_vec();
+                let count = inps.iter().sum::<u16>();
+                let mut should_be = vec![count];
+                should_be.extend(ks.iter().map(|&k| (count as usize >= k) as u16));
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // hamming_distance {{{
+    fn hamming_distance() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let n = 1 + rng.gen_usize() % 32;
+            let mut b = Builder::new();
+            let xs = b.inputs(n,2);
+            let ys = b.inputs(n,2);
+            let zs = b.hamming_distance(&xs, &ys);
+            b.outputs(&zs);
+            let c = b.finish();
+            assert_eq!(c.output_moduli(), vec![n as u16 + 1]);
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..8 {
+                let inps = (0..2*n).map(|_| rng.gen_u16() % 2).collect_vec();
+                let dist = (0..n).filter(|&i| inps[i] != inps[n+i]).count() as u16;
+                assert_eq!(c.eval(&inps), vec![dist]);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), vec![dist]);
+            }
+        }
+    }
+//}}}
+    #[test] // parity {{{
+    fn parity() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let n = 1 + rng.gen_usize() % 32;
+            let mods = (0..n).map(|_| if rng.gen_bool() { 2 } else { rng.gen_modulus() }).collect_vec();
+            let mut b = Builder::new();
+            let xs = mods.iter().map(|&q| b.input(q)).collect_vec();
+            let z = b.parity(&xs);
+            b.output(z);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            for _ in 0..16 {
+                let inps = mods.iter().map(|&q| rng.gen_u16() % q).collect_vec();
+                let should_be = inps.iter().fold(0, |acc, &x| acc ^ (x & 1));
+                assert_eq!(c.eval(&inps), vec![should_be]);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), vec![should_be]);
+            }
+        }
+    }
+//}}}
+    #[test] // one_hot {{{
+    fn one_hot() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = rng.gen_modulus();
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let zs = b.one_hot(x);
+            b.outputs(&zs);
+            let c = b.finish();
+            let (en, de, ev) = garble(&c);
+            for x in 0..q {
+                let ys = ev.eval(&c, &en.encode(&[x]));
+                let res = de.decode(&ys);
+                assert_eq!(res.len(), q as usize);
+                assert_eq!(res.iter().sum::<u16>(), 1);
+                assert_eq!(res[x as usize], 1);
+            }
+        }
+    }
+//}}}
+    #[test] // mux_tree {{{
+    fn mux_tree() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let k = rng.gen_usize() % 5;
+            let q = rng.gen_modulus();
+            let m = 1 + rng.gen_usize() % 3;
+            let mut b = Builder::new();
+            let sel = b.inputs(k,2);
+            let opts = (0..1 << k).map(|_| b.inputs(m,q)).collect_vec();
+            let zs = b.mux_tree(&sel, &opts);
+            b.outputs(&zs);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            let vals = (0..(1 << k) * m).map(|_| rng.gen_u16() % q).collect_vec();
+            for i in 0..1 << k {
+                let mut inps = numbers::u128_to_bits(i as u128, k);
+                inps.extend(&vals);
+                let should_be = vals[i*m..(i+1)*m].to_vec();
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // oblivious_read {{{
+    fn oblivious_read() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let n = 2 + rng.gen_usize() % 6;
+            let q = rng.gen_modulus();
+            let m = 1 + rng.gen_usize() % 3;
+            let mut b = Builder::new();
+            let idx = b.input(n as u16);
+            let array = (0..n).map(|_| b.inputs(m,q)).collect_vec();
+            let zs = b.oblivious_read(&array, idx);
+            b.outputs(&zs);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            let vals = (0..n * m).map(|_| rng.gen_u16() % q).collect_vec();
+            for i in 0..n {
+                let mut inps = vec![i as u16];
+                inps.extend(&vals);
+                let should_be = vals[i*m..(i+1)*m].to_vec();
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // oblivious_write {{{
+    fn oblivious_write() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let n = 2 + rng.gen_usize() % 6;
+            let q = rng.gen_modulus();
+            let m = 1 + rng.gen_usize() % 3;
+            let mut b = Builder::new();
+            let idx = b.input(n as u16);
+            let array = (0..n).map(|_| b.inputs(m,q)).collect_vec();
+            let value = b.inputs(m,q);
+            let zs = b.oblivious_write(&array, idx, &value);
+            for z in zs.iter() {
+                b.outputs(z);
+            }
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+            let vals = (0..(n+1) * m).map(|_| rng.gen_u16() % q).collect_vec();
+            for i in 0..n {
+                let mut inps = vec![i as u16];
+                inps.extend(&vals);
+                let mut should_be = vals[..n*m].to_vec();
+                should_be[i*m..(i+1)*m].copy_from_slice(&vals[n*m..]);
+                assert_eq!(c.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // from_one_hot {{{
+    fn from_one_hot() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let n = 2 + rng.gen_usize() % 30;
+            let mut b = Builder::new();
+            let bits = b.inputs(n,2);
+            let z = b.from_one_hot(&bits);
+            b.output(z);
+            let c = b.finish();
+            assert_eq!(c.output_moduli(), vec![n as u16]);
+
+            let (en, de, ev) = garble(&c);
+            for i in 0..n {
+                let mut inps = vec![0; n];
+                inps[i] = 1;
+                assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), vec![i as u16]);
+            }
+            // not one-hot: the sum of the set indices
+            let inps = (0..n).map(|_| rng.gen_u16() % 2).collect_vec();
+            let sum = (0..n).filter(|&i| inps[i] == 1).sum::<usize>() % n;
+            assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), vec![sum as u16]);
+        }
+    }
+//}}}
+    #[test] // sub {{{
+    fn sub() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let y = b.input(q);
+            let z = b.sub(x,y);
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // cmul {{{
+    fn cmul() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let _ = b.input(q);
+            let z;
+            if q > 2 {
+                z = b.cmul(x, 2);
+            } else {
+                z = b.cmul(x, 1);
+            }
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // cmul_wide {{{
+    fn cmul_wide() {
+        let mut rng = thread_rng();
+        for &q in [32749, 16381, 113, 2].iter() {
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let c = 65536 + rng.gen_u32() % (u32::MAX - 65536);
+            let z = b.cmul_wide(x, c);
+            b.output(z);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            for _ in 0..16 {
+                let x = rng.gen_u16() % q;
+                let should_be = (x as u64 * c as u64 % q as u64) as u16;
+                assert_eq!(circ.eval(&[x]), vec![should_be]);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&[x]))), vec![should_be]);
+            }
+        }
+    }
+//}}}
+    #[test] // proj_cycle {{{
+    fn proj_cycle() {
         garble_test_helper(|q| {
-            let mut rng = thread_rng();
             let mut tab = Vec::new();
-            for _ in 0..q {
-                tab.push(rng.gen_u16() % q);
+            for i in 0..q {
+                tab.push((i + 1) % q);
             }
             let mut b = Builder::new();
-            let x = b.input(q);
-            let _ = b.input(q);
-            let z = b.proj(x, q, tab);
-            b.output(z);
-            b.finish()
-        });
+            let x = b.input(q);
+            let _ = b.input(q);
+            let z = b.proj(x, q, tab);
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // proj_rand {{{
+    fn proj_rand() {
+        garble_test_helper(|q| {
+            let mut rng = thread_rng();
+            let mut tab = Vec::new();
+            for _ in 0..q {
+                tab.push(rng.gen_u16() % q);
+            }
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let _ = b.input(q);
+            let z = b.proj(x, q, tab);
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // mod_change {{{
+    fn mod_change() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let z = b.mod_change(x,q*2);
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // yao {{{
+    fn yao() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let y = b.input(q);
+            let mut tt = Vec::new();
+            for a in 0..q {
+                let mut tt_ = Vec::new();
+                for b in 0..q {
+                    tt_.push(a * b % q);
+                }
+                tt.push(tt_);
+            }
+            let z = b.yao(x, y, q, tt);
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // checked_yao {{{
+    fn checked_yao() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let mut gb = Garbler::new(&c).checked();
+            gb.by_ref().for_each(drop);
+        }
+    }
+//}}}
+    #[test] // checked_yao_wrong_table {{{
+    #[should_panic(expected = "[check_yao]")]
+    fn checked_yao_wrong_table() {
+        let mut rng = thread_rng();
+        let mut deltas = HashMap::new();
+        for &q in [3,5].iter() {
+            deltas.insert(q, Wire::rand_delta(&mut rng, q));
+        }
+        let A = Wire::rand(&mut rng, 3);
+        let B = Wire::rand(&mut rng, 5);
+        let tt = (0..3).map(|x| (0..5).map(|y| (x * y) % 5).collect_vec()).collect_vec();
+        let (C, gate) = operations::garble_yao(&A, &B, 5, &tt, 0, &deltas, &AES);
+        operations::check_yao(&A, &B, 5, &tt, 0, &deltas, &AES, &C, gate.as_ref().unwrap());
+
+        // checking against a table other than the one garbled must fail
+        let mut wrong = tt.clone();
+        wrong[1][2] = (wrong[1][2] + 1) % 5;
+        operations::check_yao(&A, &B, 5, &wrong, 0, &deltas, &AES, &C, gate.as_ref().unwrap());
+    }
+//}}}
+    #[test] // mod_secret {{{
+    fn mod_secret() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let xmod = 2 + rng.gen_u16() % 20;
+            let dmod = 2 + rng.gen_u16() % 10;
+            let mut b = Builder::new();
+            let x = b.input(xmod);
+            let d = b.input(dmod);
+            let z = b.mod_secret(x, d, dmod);
+            b.output(z);
+            let c = b.finish();
+            let (en, de, ev) = garble(&c);
+            for x in 0..xmod {
+                for d in 0..dmod {
+                    let should_be = if d == 0 { 0 } else { x % d };
+                    assert_eq!(c.eval(&[x,d])[0], should_be);
+                    let ys = ev.eval(&c, &en.encode(&[x,d]));
+                    assert_eq!(de.decode(&ys)[0], should_be);
+                }
+            }
+        }
+    }
+//}}}
+    #[test] // half_gate {{{
+    fn half_gate() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let y = b.input(q);
+            let z = b.half_gate(x,y);
+            b.output(z);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // half_gate_unequal_mods {{{
+    fn half_gate_unequal_mods() {
+        for q in 3..16 {
+            let ymod = 2 + thread_rng().gen_u16() % 6; // lower mod is capped at 8 for now
+            println!("\nTESTING MOD q={} ymod={}", q, ymod);
+
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let y = b.input(ymod);
+            let z = b.half_gate(x,y);
+            b.output(z);
+            let c = b.finish();
+
+            let (en, de, ev) = garble(&c);
+
+            let mut fail = false;
+            for x in 0..q {
+                for y in 0..ymod {
+                    println!("TEST x={} y={}", x,y);
+                    let xs = &en.encode(&[x,y]);
+                    let ys = &ev.eval(&c, xs);
+                    let decoded = de.decode(ys)[0];
+                    let should_be = c.eval(&[x,y])[0];
+                    if decoded != should_be {
+                        println!("FAILED inp={:?} q={} got={} should_be={}", [x,y], q, decoded, should_be);
+                        fail = true;
+                    } else {
+                        // println!("SUCCEEDED inp={:?} q={} got={} should_be={}", [x,y], q, decoded, should_be);
+                    }
+                }
+            }
+            if fail {
+                panic!("failed!")
+            }
+        }
+    }
+//}}}
+    #[test] // base_q_addition_no_carry {{{
+    fn base_q_addition_no_carry() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let n = 16;
+            let xs = b.inputs(n,q);
+            let ys = b.inputs(n,q);
+            let zs = b.addition_no_carry(&xs, &ys);
+            b.outputs(&zs);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // base_q_subtraction_no_borrow {{{
+    fn base_q_subtraction_no_borrow() {
+        garble_test_helper(|q| {
+            let mut b = Builder::new();
+            let n = 16;
+            let xs = b.inputs(n,q);
+            let ys = b.inputs(n,q);
+            let zs = b.subtraction_no_borrow(&xs, &ys);
+            b.outputs(&zs);
+            b.finish()
+        });
+    }
+//}}}
+    #[test] // prefix_sum {{{
+    fn prefix_sum() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let k = 1 + rng.gen_usize() % 9;
+
+            let mut b = Builder::new();
+            let xs = (0..k).map(|_| b.inputs(n,q)).collect_vec();
+            let sums = b.prefix_sum(&xs);
+            assert_eq!(sums.len(), k);
+            let width = sums[0].len();
+            assert!((q as u128).pow((width - n) as u32) >= k as u128);
+            for s in sums.iter() {
+                assert_eq!(s.len(), width);
+                b.outputs(s);
+            }
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let Q = (q as u128).pow(n as u32);
+            let mods = vec![q; width];
+            for _ in 0..8 {
+                let vals = (0..k).map(|_| rng.gen_u128() % Q).collect_vec();
+                let inps = vals.iter().flat_map(|&x| numbers::as_base_q(x,q,n)).collect_vec();
+                let outs = de.decode(&ev.eval(&circ, &en.encode(&inps)));
+                assert_eq!(outs, circ.eval(&inps));
+                let mut total = 0;
+                for (i, ds) in outs.chunks(width).enumerate() {
+                    total += vals[i];
+                    assert_eq!(numbers::from_mixed_radix(ds, &mods), total);
+                }
+            }
+        }
+    }
+//}}}
+    #[test] // in_range {{{
+    fn in_range() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+            let a = rng.gen_u128() % Q;
+            let b = rng.gen_u128() % Q;
+            let (lo, hi) = (a.min(b), a.max(b));
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            let z = b.in_range(&xs, lo, hi);
+            b.output(z);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let mut vals = vec![0, lo, hi, Q-1, lo.saturating_sub(1), (hi+1).min(Q-1)];
+            vals.extend((0..16).map(|_| rng.gen_u128() % Q));
+            for x in vals {
+                let ds = numbers::as_base_q(x,q,n);
+                let should_be = vec![(lo <= x && x <= hi) as u16];
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // size_by_modulus {{{
+    fn size_by_modulus() {
+        let mut b = Builder::new();
+        let x = b.input(5);
+        let y = b.input(5);
+        let z = b.half_gate(x,y);
+        let w = b.mod_change(z,7);
+        let k = b.constant(3,7);
+        let v = b.add(w,k);
+        let u = b.mod_change(v,2);
+        b.output(u);
+        let c = b.finish();
+        let (_, _, ev) = garble(&c);
+
+        let sizes = ev.size_by_modulus(&c);
+        assert_eq!(sizes[&5], c.ciphertexts(&c.gates[z]));
+        assert_eq!(sizes[&7], 4 + 1);
+        assert_eq!(sizes[&2], 6);
+        assert_eq!(sizes.values().sum::<usize>(), ev.size());
+
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7,113]);
+            let (_, _, ev) = garble(&c);
+            assert_eq!(ev.size_by_modulus(&c).values().sum::<usize>(), ev.size());
+        }
+    }
+//}}}
+    #[test] // ciphertext_bytes {{{
+    fn ciphertext_bytes() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7,113]);
+            let (en, de, ev) = garble(&c);
+            let bs = ev.ciphertext_bytes();
+            assert_eq!(bs.len(), 16 * ev.size());
+            let ev2 = Evaluator::from_ciphertext_bytes(&c, &bs).unwrap();
+            assert_eq!(ev2, ev);
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            let xs = en.encode(&inps);
+            assert_eq!(de.decode(&ev2.eval(&c, &xs)), c.eval(&inps));
+            assert!(Evaluator::from_ciphertext_bytes(&c, &bs[..bs.len()/2]).is_err() || bs.is_empty());
+        }
+    }
+//}}}
+    #[test] // layout {{{
+    fn layout() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7,113]);
+            let (_, _, ev) = garble(&c);
+            let bs = ev.ciphertext_bytes();
+            let layout = ev.layout(&c);
+            assert_eq!(layout.len(), c.num_nonfree_gates + c.const_refs.len());
+            let mut offset = 0;
+            for l in layout.iter() {
+                assert_eq!(l.offset, offset);
+                offset += l.len;
+                if l.kind == GateKind::Const {
+                    let x = ev.consts[l.id].as_u128().to_le_bytes();
+                    assert_eq!(&bs[l.offset..l.offset + l.len], &x[..]);
+                } else {
+                    let g = ev.gate(l.id).unwrap();
+                    assert_eq!(l.len, 16 * g.len());
+                    let i = l.offset + 16 * (rng.gen_usize() % g.len().max(1));
+                    if !g.is_empty() {
+                        assert_eq!(&bs[i..i+16], &g[(i - l.offset) / 16].to_le_bytes()[..]);
+                    }
+                }
+            }
+            assert_eq!(offset, bs.len());
+        }
+    }
+//}}}
+    #[test] // evaluator_gates {{{
+    fn evaluator_gates() {
+        let mut rng = thread_rng();
+        let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+        let mut gb = Garbler::new(&c);
+        let gates = gb.by_ref().collect_vec();
+        let ev = Evaluator::new(gates.clone(), gb.consts());
+        assert_eq!(ev.num_gates(), c.num_nonfree_gates);
+        for (id, g) in gates.iter().enumerate() {
+            assert_eq!(ev.gate(id), Some(g));
+        }
+        assert_eq!(ev.gate(gates.len()), None);
+    }
+//}}}
+    #[test] // encode_labeled {{{
+    fn encode_labeled() {
+        let mut b = Builder::new();
+        let x = b.input(5);
+        let y = b.input(3);
+        let z = b.half_gate(x,y);
+        b.output(z);
+        let c = b.finish();
+        let (en, de, ev) = garble(&c);
+
+        let xs = EncodedInputs::from_bytes(&en.encode_labeled(&[4,2]).unwrap().to_bytes()).unwrap();
+        assert!(xs.verify_against(&c).is_ok());
+        assert_eq!(xs.wires(), en.encode(&[4,2]));
+        assert_eq!(de.decode(&ev.eval(&c, &xs.wires())), vec![3]);
+
+        assert!(en.encode_labeled(&[4]).is_err());
+        assert!(en.encode_labeled(&[5,2]).is_err());
+
+        let mut bad = xs.clone();
+        bad.inputs[1].modulus = 5;
+        assert!(bad.verify_against(&c).is_err());
+        let mut bad = xs.clone();
+        bad.inputs[1].wire = Wire::zero(5);
+        assert!(bad.verify_against(&c).is_err());
+        let mut bad = xs.clone();
+        bad.inputs.swap(0,1);
+        assert!(bad.verify_against(&c).is_err());
+        let mut bad = xs.clone();
+        bad.inputs.pop();
+        assert!(bad.verify_against(&c).is_err());
+    }
+//}}}
+    #[test] // eval_with_offsets {{{
+    fn eval_with_offsets() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let (en, de, ev) = garble(&c);
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            let xs = en.encode(&inps);
+            let offsets = (0..c.ninputs()).map(|i| Wire::rand(&mut rng, c.input_mod(i))).collect_vec();
+            let shifted = xs.iter().zip(offsets.iter()).map(|(x,o)| x.plus(o)).collect_vec();
+            let ys = ev.eval_with_offsets(&c, &shifted, &offsets);
+            assert_eq!(ys, ev.eval(&c, &xs));
+            assert_eq!(de.decode(&ys), c.eval(&inps));
+        }
+    }
+//}}}
+    #[test] // decode_all_matches {{{
+    fn decode_all_matches() {
+        let mut rng = thread_rng();
+        let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[3,5,7]);
+        let (en, de, ev) = garble(&c);
+        let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+        let ys = ev.eval(&c, &en.encode(&inps));
+        let outs = c.eval(&inps);
+        assert_eq!(de.decode_all_matches(&ys).unwrap(), outs.iter().map(|&k| vec![k]).collect_vec());
+        assert!(de.decode_all_matches(&ys[1..]).is_err());
+
+        // a wire of the wrong modulus is an error rather than matching nothing
+        let mut wrong = ys.clone();
+        wrong[0] = Wire::zero(if de.output_moduli[0] == 3 { 5 } else { 3 });
+        assert!(de.decode_all_matches(&wrong).is_err());
+
+        // make the first output's table ambiguous
+        let k = outs[0];
+        let other = (k + 1) % de.output_moduli[0];
+        let mut tables = de.outputs.clone();
+        tables[0][other as usize] = ys[0].hash(operations::output_tweak(0, other));
+        let bad = Decoder::new(tables, de.output_moduli.clone());
+        let mut both = vec![k, other];
+        both.sort();
+        assert_eq!(bad.decode_all_matches(&ys).unwrap()[0], both);
+        assert_eq!(bad.decode(&ys)[0], both[0]);
+
+        // the same, hashing with another cipher
+        let base = rng.gen_u128();
+        let (en, de, ev) = garble_with_tweak_base(&c, base);
+        let ys = ev.eval_with_tweak_base(&c, &en.encode(&inps), base);
+        let matches = de.decode_all_matches_with_cipher(&ys, &TweakOffset::new(base)).unwrap();
+        assert_eq!(matches, outs.iter().map(|&k| vec![k]).collect_vec());
+    }
+//}}}
+    #[test] // decode_structured {{{
+    fn decode_structured() {
+        let mut rng = thread_rng();
+        let ps = [3,5,7,11];
+        let P = numbers::product(&ps);
+        let mut b = Builder::new();
+        let xs = ps.iter().map(|&p| b.input(p)).collect_vec();
+        let ys = ps.iter().map(|&p| b.input(p)).collect_vec();
+        let zs = xs.iter().zip(ys.iter()).map(|(&x,&y)| b.add(x,y)).collect_vec();
+        let us = b.inputs(3,10);
+        let vs = b.inputs(3,10);
+        let ws = b.addition_no_carry(&us, &vs);
+        b.outputs(&ws);
+        b.outputs(&zs);
+        let c = b.finish();
+        let (en, de, ev) = garble(&c);
+
+        let layout = [
+            OutputBundle { start: 3, len: 4, base: OutputBase::Crt },
+            OutputBundle { start: 0, len: 3, base: OutputBase::MixedRadix },
+        ];
+        for _ in 0..16 {
+            let (x, y) = (rng.gen_u128() % P, rng.gen_u128() % P);
+            let (u, v) = (rng.gen_u128() % 1000, rng.gen_u128() % 1000);
+            let mut inps = numbers::crt(&ps, x);
+            inps.extend(numbers::crt(&ps, y));
+            inps.extend(numbers::as_base_q(u,10,3));
+            inps.extend(numbers::as_base_q(v,10,3));
+            let outs = ev.eval(&c, &en.encode(&inps));
+            assert_eq!(de.decode_structured(&outs, &layout).unwrap(), vec![(x+y) % P, (u+v) % 1000]);
+        }
+
+        let outs = ev.eval(&c, &en.encode(&vec![0; c.ninputs()]));
+        let bad = |layout: &[(usize, usize)]| {
+            let layout = layout.iter().map(|&(start, len)| {
+                OutputBundle { start, len, base: OutputBase::MixedRadix }
+            }).collect_vec();
+            de.decode_structured(&outs, &layout).is_err()
+        };
+        assert!(!bad(&[(0,3), (3,4)]));
+        assert!(bad(&[(0,3)]));
+        assert!(bad(&[(0,3), (2,5)]));
+        assert!(bad(&[(0,3), (3,5)]));
+        assert!(bad(&[(0,3), (3,0), (3,4)]));
+    }
+//}}}
+    #[test] // decode_named {{{
+    fn decode_named() {
+        let mut rng = thread_rng();
+        let mut b = Builder::new();
+        let x = b.input(17);
+        let y = b.input(17);
+        let s = b.add(x,y);
+        let p = b.half_gate(x,y);
+        b.output_named(s, "sum");
+        b.output(x);
+        b.output_named(p, "product");
+        let c = b.finish();
+
+        let (en, de, ev) = garble(&c);
+        assert_eq!(de, Decoder::from_bytes(&de.to_bytes()).unwrap());
+        for _ in 0..16 {
+            let inps = vec![rng.gen_u16() % 17, rng.gen_u16() % 17];
+            let ys = ev.eval(&c, &en.encode(&inps));
+            let named = de.decode_named(&ys).unwrap();
+            assert_eq!(named.len(), 2);
+            assert_eq!(named["sum"], (inps[0] + inps[1]) % 17);
+            assert_eq!(named["product"], (inps[0] * inps[1]) % 17);
+            assert!(de.decode_named(&ys[..2]).is_err());
+        }
+    }
+//}}}
+    #[test] // decode_one {{{
+    fn decode_one() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let (en, de, ev) = garble(&c);
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            let ys = ev.eval(&c, &en.encode(&inps));
+            let outs = (0..ys.len()).map(|i| de.decode_one(i, &ys[i]).unwrap()).collect_vec();
+            assert_eq!(outs, de.decode(&ys));
+            assert!(de.decode_one(ys.len(), &ys[0]).is_err());
+        }
+    }
+//}}}
+    #[test] // garble_to_bytes {{{
+    fn garble_to_bytes() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            let (bs, de) = super::garble_to_bytes(&c, &inps).unwrap();
+            let ys = eval_from_bytes(&c, &bs).unwrap();
+            assert_eq!(de.decode(&ys), c.eval(&inps));
+            assert!(eval_from_bytes(&c, &bs[..bs.len()/2]).is_err());
+        }
+        let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+        assert!(super::garble_to_bytes(&c, &[]).is_err());
+    }
+//}}}
+    #[test] // barrett_reduce {{{
+    fn barrett_reduce() {
+        let mut rng = thread_rng();
+        for i in 0..16 {
+            let n = 1 + rng.gen_usize() % 5;
+            let p = rng.gen_prime();
+            let ms = (0..n).map(|_| if i % 4 == 0 { p } else { 2 + rng.gen_u16() % 200 }).collect_vec();
+            let Q = ms.iter().map(|&m| m as u128).product::<u128>();
+
+            let mut b = Builder::new();
+            let xs = ms.iter().map(|&m| b.input(m)).collect_vec();
+            let z = b.barrett_reduce(&xs, p);
+            b.output(z);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let mut vals = vec![0, Q-1, (p as u128).min(Q-1), (p as u128 - 1).min(Q-1)];
+            vals.extend((0..16).map(|_| rng.gen_u128() % Q));
+            for x in vals {
+                let ds = numbers::as_mixed_radix(x, &ms);
+                let should_be = vec![(x % p as u128) as u16];
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // is_member {{{
+    fn is_member() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+            let set = (0..rng.gen_usize() % 8).map(|_| rng.gen_u128() % (Q + 2)).collect_vec();
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            let ys = b.inputs(n,q);
+            let m = b.is_member(&xs, &set);
+            let e = b.eq(&xs, &ys);
+            b.output(m);
+            b.output(e);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let mut vals = set.iter().cloned().filter(|&v| v < Q).collect_vec();
+            vals.extend((0..8).map(|_| rng.gen_u128() % Q));
+            for x in vals {
+                let y = if rng.gen_bool() { x } else { rng.gen_u128() % Q };
+                let mut inps = numbers::as_base_q(x,q,n);
+                inps.extend(numbers::as_base_q(y,q,n));
+                let should_be = vec![set.contains(&x) as u16, (x == y) as u16];
+                assert_eq!(circ.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // compare_many {{{
+    fn compare_many() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+            let mut ts = vec![0, 1, Q-1, Q, Q+1];
+            ts.extend((0..8).map(|_| rng.gen_u128() % Q));
+            ts.push(ts[5]);
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            let zs = b.compare_many(&xs, &ts);
+            b.outputs(&zs);
+            let circ = b.finish();
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            for &t in ts.iter().filter(|&&t| t < Q) {
+                let cs = numbers::as_base_q(t,q,n).into_iter().map(|c| b.constant(c,q)).collect_vec();
+                let z = b.less_than(&xs, &cs);
+                b.output(z);
+            }
+            assert!(circ.stats().ciphertexts < b.finish().stats().ciphertexts);
+
+            let (en, de, ev) = garble(&circ);
+            let mut vals = vec![0, Q-1];
+            vals.extend(ts.iter().filter(|&&t| t < Q));
+            vals.extend((0..8).map(|_| rng.gen_u128() % Q));
+            for x in vals {
+                let ds = numbers::as_base_q(x,q,n);
+                let should_be = ts.iter().map(|&t| (x < t) as u16).collect_vec();
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // bucketize {{{
+    fn bucketize() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+            let mut bounds = (0..2 + rng.gen_usize() % 6).map(|_| rng.gen_u128() % (Q + 1)).collect_vec();
+            bounds.sort();
+            bounds.dedup();
+            if bounds.len() < 2 {
+                continue;
+            }
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            let zs = b.bucketize(&xs, &bounds);
+            b.outputs(&zs);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let mut vals = vec![0, Q-1];
+            vals.extend(bounds.iter().flat_map(|&t| vec![t.saturating_sub(1), t]).filter(|&t| t < Q));
+            vals.extend((0..8).map(|_| rng.gen_u128() % Q));
+            for x in vals {
+                let ds = numbers::as_base_q(x,q,n);
+                let should_be = bounds.windows(2).map(|w| (w[0] <= x && x < w[1]) as u16).collect_vec();
+                assert!(should_be.iter().sum::<u16>() <= 1);
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // compare3 {{{
+    fn compare3() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            let ys = b.inputs(n,q);
+            let z = b.compare3(&xs, &ys);
+            b.output(z);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            for _ in 0..16 {
+                let x = rng.gen_u128() % Q;
+                let y = if rng.gen_bool() { x } else { rng.gen_u128() % Q };
+                let mut inps = numbers::as_base_q(x,q,n);
+                inps.extend(numbers::as_base_q(y,q,n));
+                let should_be = vec![match x.cmp(&y) {
+                    std::cmp::Ordering::Less    => 0,
+                    std::cmp::Ordering::Equal   => 1,
+                    std::cmp::Ordering::Greater => 2,
+                }];
+                assert_eq!(circ.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // select_by_compare {{{
+    fn select_by_compare() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let p = rng.gen_modulus();
+            let m = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            let ys = b.inputs(n,q);
+            let ts = b.inputs(m,p);
+            let es = b.inputs(m,p);
+            let zs = b.select_by_compare(&xs, &ys, &ts, &es);
+            b.outputs(&zs);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            for _ in 0..16 {
+                let x = rng.gen_u128() % Q;
+                let y = if rng.gen_bool() { x } else { rng.gen_u128() % Q };
+                let t = (0..m).map(|_| rng.gen_u16() % p).collect_vec();
+                let e = (0..m).map(|_| rng.gen_u16() % p).collect_vec();
+                let mut inps = numbers::as_base_q(x,q,n);
+                inps.extend(numbers::as_base_q(y,q,n));
+                inps.extend(&t);
+                inps.extend(&e);
+                let should_be = if x < y { t } else { e };
+                assert_eq!(circ.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // fold {{{
+    fn fold() {
+        let mut rng = thread_rng();
+        for &op in [FoldOp::Add, FoldOp::Max, FoldOp::Min].iter() {
+            for _ in 0..4 {
+                let q = 2 + rng.gen_u16() % 15;
+                let n = 1 + rng.gen_usize() % 4;
+                let k = rng.gen_usize() % 6;
+                let Q = (q as u128).pow(n as u32);
+
+                let mut b = Builder::new();
+                let init = b.inputs(n,q);
+                let items = (0..k).map(|_| b.inputs(n,q)).collect_vec();
+                let zs = b.fold(&init, &items, op);
+                b.outputs(&zs);
+                let circ = b.finish();
+
+                let (en, de, ev) = garble(&circ);
+                for _ in 0..8 {
+                    let vals = (0..=k).map(|_| rng.gen_u128() % Q).collect_vec();
+                    let res = vals[1..].iter().fold(vals[0], |acc, &x| match op {
+                        FoldOp::Add => (acc + x) % Q,
+                        FoldOp::Max => acc.max(x),
+                        FoldOp::Min => acc.min(x),
+                    });
+                    let inps = vals.iter().flat_map(|&x| numbers::as_base_q(x,q,n)).collect_vec();
+                    let should_be = numbers::as_base_q(res,q,n);
+                    assert_eq!(circ.eval(&inps), should_be);
+                    assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&inps))), should_be);
+                }
+            }
+        }
+    }
+//}}}
+    #[test] // masked_add {{{
+    fn masked_add() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let k = 1 + rng.gen_usize() % 8;
+            let Q = (q as u128).pow(n as u32);
+
+            let mut b = Builder::new();
+            let mut acc = b.inputs(n,q);
+            for _ in 0..k {
+                let value = b.inputs(n,q);
+                let include = b.input(2);
+                acc = b.masked_add(&acc, &value, include);
+            }
+            b.outputs(&acc);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            for _ in 0..8 {
+                let init = rng.gen_u128() % Q;
+                let mut inps = numbers::as_base_q(init,q,n);
+                let mut sum = init;
+                for _ in 0..k {
+                    let v = rng.gen_u128() % Q;
+                    let include = rng.gen_bool();
+                    inps.extend(numbers::as_base_q(v,q,n));
+                    inps.push(include as u16);
+                    if include {
+                        sum = (sum + v) % Q;
+                    }
+                }
+                let should_be = numbers::as_base_q(sum,q,n);
+                assert_eq!(circ.eval(&inps), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&inps))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // sort2 {{{
+    fn sort2() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+
+            let mut b = Builder::new();
+            let xs = b.inputs(n,q);
+            let ys = b.inputs(n,q);
+            let (lo, hi) = b.sort2(&xs, &ys);
+            b.outputs(&lo);
+            b.outputs(&hi);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            for i in 0..8 {
+                let x = rng.gen_u128() % Q;
+                let y = if i == 0 { x } else { rng.gen_u128() % Q };
+                let mut inps = numbers::as_base_q(x,q,n);
+                inps.extend(numbers::as_base_q(y,q,n));
+                let mut should_be = numbers::as_base_q(x.min(y),q,n);
+                should_be.extend(numbers::as_base_q(x.max(y),q,n));
+                assert_eq!(circ.eval(&inps), should_be);
+                let outs = de.decode(&ev.eval(&circ, &en.encode(&inps)));
+                assert!(numbers::from_base_q(&outs[..n],q) <= numbers::from_base_q(&outs[n..],q));
+                assert_eq!(outs, should_be);
+            }
+        }
     }
 //}}}
-    #[test] // mod_change {{{
-    fn mod_change() {
-        garble_test_helper(|q| {
+    #[test] // clamp {{{
+    fn clamp() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+            let a = rng.gen_u128() % Q;
+            let b = rng.gen_u128() % Q;
+            let (lo, hi) = (a.min(b), a.max(b));
+
             let mut b = Builder::new();
-            let x = b.input(q);
-            let z = b.mod_change(x,q*2);
-            b.output(z);
-            b.finish()
-        });
+            let xs = b.inputs(n,q);
+            let zs = b.clamp(&xs, lo, hi);
+            b.outputs(&zs);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let mut vals = vec![0, lo, hi, Q-1, lo.saturating_sub(1), (hi+1).min(Q-1)];
+            vals.extend((0..16).map(|_| rng.gen_u128() % Q));
+            for x in vals {
+                let ds = numbers::as_base_q(x,q,n);
+                let should_be = numbers::as_base_q(x.max(lo).min(hi),q,n);
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
     }
 //}}}
-    #[test] // yao {{{
-    fn yao() {
-        garble_test_helper(|q| {
+    #[test] // sum_bundles {{{
+    fn sum_bundles() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let k = 1 + rng.gen_usize() % 7;
+
             let mut b = Builder::new();
-            let x = b.input(q);
-            let y = b.input(q);
-            let mut tt = Vec::new();
-            for a in 0..q {
-                let mut tt_ = Vec::new();
-                for b in 0..q {
-                    tt_.push(a * b % q);
+            let xs = (0..k).map(|_| b.inputs(n,q)).collect_vec();
+            let zs = b.sum_bundles(&xs);
+            b.outputs(&zs);
+            let circ = b.finish();
+
+            let width = n + (k as f64).log2().ceil() as usize;
+            assert_eq!(zs.len(), width);
+
+            let (en, de, ev) = garble(&circ);
+            let Q = (q as u128).pow(n as u32);
+            let mods = vec![q; width];
+            for _ in 0..16 {
+                let mut should_be = 0;
+                let mut ds = Vec::new();
+                for _ in 0..k {
+                    let x = rng.gen_u128() % Q;
+                    should_be += x;
+                    ds.extend(numbers::as_base_q(x,q,n));
                 }
-                tt.push(tt_);
+                assert_eq!(numbers::from_mixed_radix(&circ.eval(&ds), &mods), should_be);
+                let ys = ev.eval(&circ, &en.encode(&ds));
+                assert_eq!(numbers::from_mixed_radix(&de.decode(&ys), &mods), should_be);
             }
-            let z = b.yao(x, y, q, tt);
-            b.output(z);
-            b.finish()
-        });
+        }
     }
 //}}}
-    #[test] // half_gate {{{
-    fn half_gate() {
-        garble_test_helper(|q| {
+    #[test] // increment {{{
+    fn increment() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+            let by = (rng.gen_u128() % Q.min(1 << 16)) as u16;
+
             let mut b = Builder::new();
-            let x = b.input(q);
-            let y = b.input(q);
-            let z = b.half_gate(x,y);
-            b.output(z);
-            b.finish()
-        });
+            let xs = b.inputs(n,q);
+            let (zs, overflow) = b.increment(&xs, by);
+            b.outputs(&zs);
+            b.output(overflow);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            for _ in 0..16 {
+                // stay close to the maximum value so that overflow happens often
+                let x = Q - 1 - rng.gen_u128() % (2 * by as u128 + 1).min(Q);
+                let ds = numbers::as_base_q(x,q,n);
+                let res = de.decode(&ev.eval(&circ, &en.encode(&ds)));
+                assert_eq!(res, circ.eval(&ds));
+                assert_eq!(numbers::from_base_q(&res[..n], q), (x + by as u128) % Q);
+                assert_eq!(res[n], (x + by as u128 >= Q) as u16);
+            }
+        }
     }
 //}}}
-    #[test] // half_gate_unequal_mods {{{
-    fn half_gate_unequal_mods() {
-        for q in 3..16 {
-            let ymod = 2 + thread_rng().gen_u16() % 6; // lower mod is capped at 8 for now
-            println!("\nTESTING MOD q={} ymod={}", q, ymod);
-
+    #[test] // fixed_mul {{{
+    fn fixed_mul() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let n = 2 + rng.gen_usize() % 15;
+            let s = rng.gen_usize() % (n+1);
             let mut b = Builder::new();
-            let x = b.input(q);
-            let y = b.input(ymod);
-            let z = b.half_gate(x,y);
-            b.output(z);
+            let xs = b.inputs(n,2);
+            let ys = b.inputs(n,2);
+            let zs = b.fixed_mul(&xs, &ys, s);
+            b.outputs(&zs);
             let c = b.finish();
 
             let (en, de, ev) = garble(&c);
-
-            let mut fail = false;
-            for x in 0..q {
-                for y in 0..ymod {
-                    println!("TEST x={} y={}", x,y);
-                    let xs = &en.encode(&[x,y]);
-                    let ys = &ev.eval(&c, xs);
-                    let decoded = de.decode(ys)[0];
-                    let should_be = c.eval(&[x,y])[0];
-                    if decoded != should_be {
-                        println!("FAILED inp={:?} q={} got={} should_be={}", [x,y], q, decoded, should_be);
-                        fail = true;
-                    } else {
-                        // println!("SUCCEEDED inp={:?} q={} got={} should_be={}", [x,y], q, decoded, should_be);
-                    }
+            for _ in 0..16 {
+                let x = rng.gen_u128() % (1 << n);
+                let y = rng.gen_u128() % (1 << n);
+                let mut inps = numbers::u128_to_bits(x,n);
+                inps.extend(numbers::u128_to_bits(y,n));
+                let z = numbers::u128_from_bits(&de.decode(&ev.eval(&c, &en.encode(&inps))));
+                assert_eq!(numbers::u128_from_bits(&c.eval(&inps)), z);
+
+                let exact = (x * y) as f64 / (1u128 << s) as f64;
+                let should_be = ((x * y) >> s) % (1 << n);
+                assert_eq!(z, should_be);
+                if exact < (1u128 << n) as f64 {
+                    assert!(exact - (z as f64) < 1.0);
                 }
             }
-            if fail {
-                panic!("failed!")
+        }
+    }
+//}}}
+    #[test] // signum {{{
+    fn signum() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let n = 2 + rng.gen_usize() % 7;
+            let mut b = Builder::new();
+            let xs = b.inputs(n,2);
+            let s = b.sign(&xs);
+            let t = b.signum(&xs);
+            b.output(s);
+            b.output(t);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let half = 1_i64 << (n-1);
+            let mut vals = vec![0, 1, -1, half - 1, -half];
+            vals.extend((0..16).map(|_| rng.gen_u128() as i64 % half));
+            for x in vals {
+                let ds = numbers::u128_to_bits(x as u128 & ((1 << n) - 1), n);
+                let should_be = vec![(x < 0) as u16, if x < 0 { 2 } else { (x > 0) as u16 }];
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
             }
         }
     }
 //}}}
-    #[test] // base_q_addition_no_carry {{{
-    fn base_q_addition_no_carry() {
-        garble_test_helper(|q| {
+    #[test] // signed_mul {{{
+    fn signed_mul() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let n = 1 + rng.gen_usize() % 8;
+            let m = 1 + rng.gen_usize() % 8;
+            let mut b = Builder::new();
+            let xs = b.inputs(n,2);
+            let ys = b.inputs(m,2);
+            let zs = b.signed_mul(&xs, &ys);
+            b.outputs(&zs);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let (xhalf, yhalf) = (1_i64 << (n-1), 1_i64 << (m-1));
+            for i in 0..16 {
+                let x = match i { 0 => -xhalf, 1 => xhalf - 1, _ => rng.gen_u128() as i64 % xhalf };
+                let y = match i { 0 => -yhalf, 1 => -yhalf, _ => rng.gen_u128() as i64 % yhalf };
+                let mut ds = numbers::u128_to_bits(x as u128 & ((1 << n) - 1), n);
+                ds.extend(numbers::u128_to_bits(y as u128 & ((1 << m) - 1), m));
+                let should_be = numbers::u128_to_bits((x*y) as u128 & ((1 << (n+m)) - 1), n+m);
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // float_mul {{{
+    fn float_mul() {
+        let mut rng = thread_rng();
+        let e = 6;
+        for _ in 0..8 {
+            let m = 1 + rng.gen_usize() % 10;
+            let mut b = Builder::new();
+            let float_input = |b: &mut Builder| crate::circuit::Float {
+                sign: b.input(2),
+                exponent: b.inputs(e,2),
+                mantissa: b.inputs(m,2),
+            };
+            let x = float_input(&mut b);
+            let y = float_input(&mut b);
+            let z = b.float_mul(&x, &y);
+            b.output(z.sign);
+            b.outputs(&z.exponent);
+            b.outputs(&z.mantissa);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            let value = |ds: &[u16]| {
+                let exp = numbers::u128_from_bits(&ds[1..e+1]) as i32;
+                let exp = if exp >= 1 << (e-1) { exp - (1 << e) } else { exp };
+                let mant = numbers::u128_from_bits(&ds[e+1..]) as f64;
+                let s = if ds[0] == 1 { -1.0 } else { 1.0 };
+                s * mant * 2f64.powi(exp - m as i32 + 1)
+            };
+            for _ in 0..16 {
+                // exponents in [-16,16) so that the product cannot overflow
+                let mut inps = Vec::new();
+                for _ in 0..2 {
+                    inps.push(rng.gen_u16() % 2);
+                    let exp = (rng.gen_u16() % 32) as i32 - 16;
+                    inps.extend(numbers::u128_to_bits((exp as u128) & ((1 << e) - 1), e));
+                    let mant = (1 << (m-1)) | (rng.gen_u128() % (1 << (m-1)));
+                    inps.extend(numbers::u128_to_bits(mant, m));
+                }
+                let n = 1 + e + m;
+                let exact = value(&inps[..n]) * value(&inps[n..]);
+
+                let out = circ.eval(&inps);
+                assert_eq!(out[e+m], 1, "result is not normalized");
+                let approx = value(&out);
+                assert_eq!(approx.signum(), exact.signum());
+                assert!(approx.abs() <= exact.abs());
+                assert!((exact - approx).abs() < exact.abs() * 2f64.powi(1 - m as i32));
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&inps))), out);
+            }
+        }
+    }
+//}}}
+    #[test] // div_mod {{{
+    fn div_mod() {
+        let mut rng = thread_rng();
+        for _ in 0..8 {
+            let n = 1 + rng.gen_usize() % 8;
+            let m = 1 + rng.gen_usize() % 8;
+            let mut b = Builder::new();
+            let num = b.inputs(n,2);
+            let den = b.inputs(m,2);
+            let (quot, rem) = b.div_mod(&num, &den);
+            b.outputs(&quot);
+            b.outputs(&rem);
+            let circ = b.finish();
+
+            let (en, de, ev) = garble(&circ);
+            for i in 0..16 {
+                let x = rng.gen_u128() % (1 << n);
+                let y = if i == 0 { 0 } else { 1 + rng.gen_u128() % ((1 << m) - 1) };
+                let mut inps = numbers::u128_to_bits(x, n);
+                inps.extend(numbers::u128_to_bits(y, m));
+
+                let out = de.decode(&ev.eval(&circ, &en.encode(&inps)));
+                assert_eq!(out, circ.eval(&inps));
+                let q = numbers::u128_from_bits(&out[..n]);
+                let r = numbers::u128_from_bits(&out[n..]);
+                if y == 0 {
+                    assert_eq!(q, (1 << n) - 1);
+                    assert_eq!(r, x % (1 << m));
+                } else {
+                    assert_eq!(q * y + r, x);
+                    assert!(r < y);
+                }
+            }
+        }
+    }
+//}}}
+    #[test] // isqrt {{{
+    fn isqrt() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = 2 + rng.gen_u16() % 15;
+            let n = 1 + rng.gen_usize() % 4;
+            let Q = (q as u128).pow(n as u32);
+            let nbits = 128 - (Q-1).leading_zeros() as usize;
+
             let mut b = Builder::new();
-            let n = 16;
             let xs = b.inputs(n,q);
-            let ys = b.inputs(n,q);
-            let zs = b.addition_no_carry(&xs, &ys);
+            let zs = b.isqrt(&xs);
             b.outputs(&zs);
-            b.finish()
-        });
+            let circ = b.finish();
+            assert_eq!(zs.len(), nbits.div_ceil(2));
+
+            let (en, de, ev) = garble(&circ);
+            let r = (((Q-1) as f64).sqrt().floor()) as u128;
+            let mut vals = vec![0, 1, 2, 3, 4, Q-1, r*r, r*r-1, (r-1)*(r-1)];
+            vals.extend((0..16).map(|_| rng.gen_u128() % Q));
+            for x in vals.into_iter().filter(|&x| x < Q) {
+                let mut s = (x as f64).sqrt().floor() as u128;
+                // correct for floating point error at the integer boundaries
+                while s*s > x { s -= 1; }
+                while (s+1)*(s+1) <= x { s += 1; }
+                let ds = numbers::as_base_q(x,q,n);
+                let should_be = numbers::u128_to_bits(s, zs.len());
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
+    }
+//}}}
+    #[test] // count_leading_zeros {{{
+    fn count_leading_zeros() {
+        let mut rng = thread_rng();
+        for n in 1..=17 {
+            let mut b = Builder::new();
+            let xs = b.inputs(n,2);
+            let zs = b.count_leading_zeros(&xs);
+            b.outputs(&zs);
+            let circ = b.finish();
+            assert!(1 << zs.len() > n && 1 << (zs.len() - 1) <= n);
+
+            let (en, de, ev) = garble(&circ);
+            let mut vals = vec![vec![0; n], vec![1; n]];
+            vals.extend((0..16).map(|_| {
+                // random numbers with a random number of leading zeros
+                let k = rng.gen_usize() % (n+1);
+                (0..n).map(|i| if i < k { rng.gen_u16() % 2 } else { 0 }).collect_vec()
+            }));
+            for ds in vals {
+                let clz = ds.iter().rev().take_while(|&&d| d == 0).count();
+                let should_be = numbers::u128_to_bits(clz as u128, zs.len());
+                assert_eq!(circ.eval(&ds), should_be);
+                assert_eq!(de.decode(&ev.eval(&circ, &en.encode(&ds))), should_be);
+            }
+        }
     }
 //}}}
     #[test] // fancy_addition {{{
@@ -586,6 +2889,7 @@ mod tests {
             let Y = ev.eval(&circ, &X);
             let res = de.decode(&Y);
             assert_eq!(numbers::from_mixed_radix(&res,&mods), should_be);
+            assert_eq!(ev.eval_and_decode(&circ, &X, &de).unwrap(), res);
         }
     }
 //}}}
@@ -615,6 +2919,428 @@ mod tests {
             assert_eq!(de.decode(&Y)[0], (x+c)%q, "garbled");
         }
     }
+//}}}
+    #[test] // tweak_base {{{
+    fn tweak_base() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let (b1, b2) = (rng.gen_u128(), rng.gen_u128());
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+
+            for &base in [b1, b2].iter() {
+                let (en, de, ev) = garble_with_tweak_base(&c, base);
+                let ys = ev.eval_with_tweak_base(&c, &en.encode(&inps), base);
+                let cipher = TweakOffset::new(base);
+                assert_eq!(de.try_decode_with_cipher(&ys, &cipher).unwrap(), c.eval(&inps));
+            }
+
+            // with the same randomness, only the tweaks tell the sessions apart
+            let seed = rng.gen_u128() as u64;
+            let (c1, c2) = (TweakOffset::new(b1), TweakOffset::new(b2));
+            let gates1 = Garbler::with_rng(&c, StdRng::seed_from_u64(seed)).with_cipher(&c1).collect_vec();
+            let gates2 = Garbler::with_rng(&c, StdRng::seed_from_u64(seed)).with_cipher(&c2).collect_vec();
+            let again = Garbler::with_rng(&c, StdRng::seed_from_u64(seed)).with_cipher(&c1).collect_vec();
+            assert_eq!(gates1, again);
+            if c.num_nonfree_gates > 0 {
+                assert_ne!(gates1, gates2);
+            }
+        }
+    }
+//}}}
+    #[test] // garble_segment {{{
+    fn garble_segment() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let seed = rng.gen_u128() as u64;
+            let mut gb = Garbler::with_rng(&c, StdRng::seed_from_u64(seed));
+            let en = gb.encoder();
+            let whole = gb.by_ref().collect_vec();
+            let de = gb.decoder().unwrap();
+
+            let mut gb = Garbler::with_rng(&c, StdRng::seed_from_u64(seed));
+            let mid = rng.gen_usize() % (c.gates.len() + 1);
+            let (mut gates, carry) = gb.garble_segment(0..mid, &[]);
+            assert_eq!(carry.len(), c.segment_boundary(mid).len());
+            let (rest, outs) = gb.garble_segment(mid..c.gates.len(), &carry);
+            gates.extend(rest);
+            assert_eq!(gates, whole);
+            let last = c.segment_boundary(c.gates.len());
+            assert!(c.output_refs.iter().all(|r| last.contains(r)));
+            assert_eq!(outs.len(), last.len());
+
+            let ev = Evaluator::new(gates, gb.consts());
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), c.eval(&inps));
+        }
+    }
+//}}}
+    #[test] // seeded_rng {{{
+    fn seeded_rng() {
+        let mut b = Builder::new();
+        let x = b.input(17);
+        let y = b.input(5);
+        let z = b.half_gate(x,y);
+        let w = b.mod_change(z,3);
+        b.output(w);
+        let circ = b.finish();
+
+        let mut gb1 = Garbler::with_rng(&circ, StdRng::seed_from_u64(42));
+        let mut gb2 = Garbler::with_rng(&circ, StdRng::seed_from_u64(42));
+        assert_eq!(gb1.encoder(), gb2.encoder());
+        assert_eq!(gb1.by_ref().collect_vec(), gb2.by_ref().collect_vec());
+        assert_eq!(gb1.decoder().unwrap(), gb2.decoder().unwrap());
+
+        let gb3 = Garbler::with_rng(&circ, StdRng::seed_from_u64(43));
+        assert!(gb1.encoder() != gb3.encoder());
+    }
+//}}}
+    #[test] // garblings_identical {{{
+    fn garblings_identical() {
+        let mut b = Builder::new();
+        let x = b.input(17);
+        let y = b.input(5);
+        let k = b.constant(4,5);
+        let z = b.half_gate(x,y);
+        let w = b.mod_change(z,3);
+        let v = b.add(y,k);
+        let u = b.proj(v, 7, vec![1,2,3,4,5]);
+        b.output(w);
+        b.output(u);
+        let circ = b.finish();
+
+        let seeded = |c: &Circuit, seed: u64| {
+            let mut gb = Garbler::with_rng(c, StdRng::seed_from_u64(seed));
+            let gates = gb.by_ref().collect_vec();
+            Evaluator::new(gates, gb.consts())
+        };
+        let ev = seeded(&circ, 42);
+        assert!(super::garblings_identical(&ev, &seeded(&circ, 42)));
+        assert!(!super::garblings_identical(&ev, &seeded(&circ, 43)));
+
+        // the segmented garbler must agree with the iterator
+        let mut gb = Garbler::with_rng(&circ, StdRng::seed_from_u64(42));
+        let (gates, _) = gb.garble_segment(0..circ.gates.len(), &[]);
+        assert!(super::garblings_identical(&ev, &Evaluator::new(gates, gb.consts())));
+
+        let mut changed = circ.clone();
+        if let Gate::Proj { ref mut tt, .. } = changed.gates[u] {
+            tt[0] = 0;
+        }
+        assert!(!super::garblings_identical(&ev, &seeded(&changed, 42)));
+    }
+//}}}
+    #[test] // preprocess_deltas {{{
+    fn preprocess_deltas() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let mut gb = Garbler::new(&c);
+            let before = gb.deltas().clone();
+            gb.preprocess_deltas(&[2,3,5,7,11]);
+            let deltas = gb.deltas().clone();
+            assert_eq!(deltas.len(), 5);
+            for q in before.keys() {
+                assert_eq!(before[q], deltas[q]);
+            }
+            // a later garbler can reuse them
+            let d = crate::circuit::random_circuit(&mut rng, 4, 32, &[3,11]);
+            assert_eq!(Garbler::with_deltas(&d, thread_rng(), deltas.clone()).deltas(), &deltas);
+
+            let en = gb.encoder();
+            for i in 0..c.ninputs() {
+                let q = c.input_mod(i);
+                assert_eq!(en.encode_input(1, i).minus(&en.encode_input(0, i)), deltas[&q]);
+            }
+
+            let gates = gb.by_ref().collect_vec();
+            let ev = Evaluator::new(gates, gb.consts());
+            let de = gb.decoder().unwrap();
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), c.eval(&inps));
+        }
+    }
+//}}}
+    #[test] // garbler_stats {{{
+    fn garbler_stats() {
+        let mut b = Builder::new();
+        let x = b.input(17);
+        let y = b.input(5);
+        let z = b.half_gate(x,y);
+        let w = b.mod_change(z,3);
+        let c = b.constant(1,3);
+        let v = b.add(w,c);
+        b.output(v);
+        b.output_labeled(z);
+        let circ = b.finish();
+
+        let mut gb = Garbler::new(&circ);
+        let before = gb.stats();
+        assert_eq!(before.nwires, 0);
+        gb.by_ref().for_each(drop);
+        let stats = gb.stats();
+        assert_eq!(stats.ndeltas, circ.gate_moduli.iter().unique().count());
+        assert_eq!(stats.ndeltas, 3);
+        assert_eq!(stats.ninputs, 2);
+        assert_eq!(stats.nconsts, 1);
+        assert_eq!(stats.nwires, circ.gates.len());
+        assert_eq!(stats.noutput_tables, 1);
+        assert!(stats.bytes > before.bytes);
+    }
+//}}}
+    #[cfg(feature = "debug-dump")]
+    #[test] // debug_dump {{{
+    fn debug_dump() {
+        let mut b = Builder::new();
+        let x = b.input(17);
+        let y = b.input(5);
+        let z = b.half_gate(x,y);
+        let w = b.mod_change(z,3);
+        let c = b.constant(1,3);
+        let v = b.add(w,c);
+        b.output(v);
+        let circ = b.finish();
+
+        let mut gb = Garbler::new(&circ);
+        gb.by_ref().for_each(drop);
+        let dump = gb.debug_dump();
+        assert_eq!(dump.lines().filter(|l| l.starts_with("delta ")).count(), 3);
+        assert_eq!(dump.lines().filter(|l| l.starts_with("gate ")).count(), circ.gates.len());
+    }
+//}}}
+    #[test] // decode_modulus_mismatch {{{
+    fn decode_modulus_mismatch() {
+        let mut rng = thread_rng();
+        let mut b = Builder::new();
+        let x = b.input(5);
+        let z = b.cmul(x,2);
+        b.output(z);
+        let circ = b.finish();
+        let (en, de, ev) = garble(&circ);
+        assert_eq!(de.output_moduli(), &[5]);
+
+        let ys = ev.eval(&circ, &en.encode(&[3]));
+        assert_eq!(de.try_decode(&ys).unwrap(), vec![1]);
+
+        let bad = vec![Wire::rand(&mut rng, 7)];
+        assert!(de.try_decode(&bad).is_err());
+        assert!(de.try_decode(&[]).is_err());
+    }
+//}}}
+    #[test] // counting_cipher {{{
+    fn counting_cipher() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let q = rng.gen_prime();
+            let mut b = Builder::new();
+            let x = b.input(q);
+            let y = b.input(q);
+            let z = b.half_gate(x,y);
+            b.output(z);
+            let circ = b.finish();
+
+            let cipher = CountingCipher::new();
+            let mut gb = Garbler::new(&circ).with_cipher(&cipher);
+            let en = gb.encoder();
+            let gates = gb.by_ref().collect_vec();
+            assert_eq!(cipher.count(), (q + q) as usize);
+
+            // the garbled circuit still works with the same cipher
+            let ev = Evaluator::new(gates, gb.consts());
+            let de = gb.decoder().unwrap();
+            cipher.reset();
+            let (xv, yv) = (rng.gen_u16() % q, rng.gen_u16() % q);
+            let ys = ev.eval_with_cipher(&circ, &en.encode(&[xv,yv]), &cipher);
+            assert_eq!(cipher.count(), 2);
+            assert_eq!(de.decode(&ys)[0], xv * yv % q);
+        }
+    }
+//}}}
+    #[test] // eval_with_provider {{{
+    fn eval_with_provider() {
+        let mut rng = thread_rng();
+        let moduli = [2,3,5,7];
+        for _ in 0..32 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 32, &moduli);
+            let mut gb = Garbler::new(&c);
+            let en = gb.encoder();
+            // gates arrive in arbitrary order, keyed by their id
+            let mut arrived: HashMap<Id, GarbledGate> = gb.by_ref().enumerate().collect();
+            let ev = Evaluator::new(Vec::new(), gb.consts());
+            let de = gb.decoder().unwrap();
+
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            let ys = ev.eval_with_provider(&c, &en.encode(&inps), |id| arrived.remove(&id).unwrap());
+            assert!(arrived.is_empty());
+            assert_eq!(de.decode(&ys), c.eval(&inps));
+        }
+    }
+//}}}
+    #[test] // consistency {{{
+    fn consistency() {
+        let mut b = Builder::new();
+        let x = b.input(5);
+        let y = b.input(3);
+        let z = b.half_gate(x,y);
+        let w = b.mod_change(z,7);
+        let c = b.constant(2,7);
+        let v = b.add(w,c);
+        b.output(v);
+        let c1 = b.finish();
+
+        let mut b = Builder::new();
+        let x = b.input(5);
+        let y = b.input(5);
+        let z = b.half_gate(x,y);
+        let w = b.mod_change(z,7);
+        let c = b.constant(2,7);
+        let v = b.add(w,c);
+        b.output(v);
+        let c2 = b.finish();
+
+        let (_, _, ev1) = garble(&c1);
+        let (_, _, ev2) = garble(&c2);
+        assert_eq!(ev1.is_consistent_with(&c1), Ok(()));
+        assert_eq!(ev2.is_consistent_with(&c2), Ok(()));
+        // the half gates differ in size
+        assert_eq!(ev1.is_consistent_with(&c2), Err(ConsistencyError::GateSize { id: 0, expected: 8, got: 7 }));
+
+        let ev = Evaluator::new(ev1.gates.clone(), Vec::new());
+        assert_eq!(ev.is_consistent_with(&c1), Err(ConsistencyError::ConstCount { expected: 1, got: 0 }));
+        let ev = Evaluator::new(ev1.gates[..1].to_vec(), ev1.consts.clone());
+        assert_eq!(ev.is_consistent_with(&c1), Err(ConsistencyError::GateCount { expected: 2, got: 1 }));
+    }
+//}}}
+    #[test] // pad_to {{{
+    fn pad_to() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = crate::circuit::random_circuit(&mut rng, 4, 16, &[2,3,5,7]);
+            let target = c.num_nonfree_gates + rng.gen_usize() % 16;
+            let d = c.pad_to(target);
+            assert_eq!(d.num_nonfree_gates, target);
+            assert!(d.stats().ciphertexts >= c.stats().ciphertexts);
+
+            let (en, de, ev) = garble(&d);
+            assert_eq!(ev.gates.len(), target);
+            assert_eq!(ev.is_consistent_with(&d), Ok(()));
+            for _ in 0..4 {
+                let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+                assert_eq!(d.eval(&inps), c.eval(&inps));
+                assert_eq!(de.decode(&ev.eval(&d, &en.encode(&inps))), c.eval(&inps));
+            }
+        }
+    }
+//}}}
+    #[test] // memory_limit {{{
+    fn memory_limit() {
+        let mut rng = thread_rng();
+        let c = crate::circuit::random_circuit(&mut rng, 4, 64, &[2,3,5,7]);
+        let estimate = c.estimated_garble_memory();
+        let (_, _, ev) = garble(&c);
+        let ncts: usize = ev.gates.iter().map(|g| g.len()).sum();
+        assert!(estimate >= ncts * 16);
+
+        let err = garble_with_memory_limit(&c, estimate - 1).err().unwrap();
+        assert_eq!(err.estimate, estimate);
+        assert_eq!(err.limit, estimate - 1);
+
+        let (en, de, ev) = garble_with_memory_limit(&c, estimate).unwrap();
+        let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+        assert_eq!(de.decode(&ev.eval(&c, &en.encode(&inps))), c.eval(&inps));
+    }
+//}}}
+    #[test] // labeled_outputs {{{
+    fn labeled_outputs() {
+        let mut rng = thread_rng();
+        let q = rng.gen_modulus();
+
+        // the first circuit computes x*y, leaving it as a label, and decodes x+y
+        let mut b = Builder::new();
+        let x = b.input(q);
+        let y = b.input(q);
+        let z = b.half_gate(x,y);
+        b.output_labeled(z);
+        let s = b.add(x,y);
+        b.output(s);
+        let c1 = b.finish();
+
+        // the second circuit computes (x*y)*w
+        let mut b = Builder::new();
+        let z = b.input(q);
+        let w = b.input(q);
+        let r = b.half_gate(z,w);
+        b.output(r);
+        let c2 = b.finish();
+
+        let mut gb1 = Garbler::new(&c1);
+        let en1 = gb1.encoder();
+        let ev1 = Evaluator::new(gb1.by_ref().collect(), gb1.consts());
+        let de1 = gb1.decoder().unwrap();
+        assert_eq!(gb1.output_mode(0), OutputMode::Labeled);
+        assert_eq!(gb1.output_mode(1), OutputMode::Decoded);
+
+        let base = gb1.tweak_end();
+        let mut gb2 = Garbler::with_deltas(&c2, thread_rng(), gb1.deltas().clone())
+            .chain(&gb1.labeled_outputs().unwrap(), base);
+        let en2 = gb2.encoder();
+        let ev2 = Evaluator::new(gb2.by_ref().collect(), gb2.consts());
+        let de2 = gb2.decoder().unwrap();
+
+        for _ in 0..16 {
+            let (x, y, w) = (rng.gen_u16() % q, rng.gen_u16() % q, rng.gen_u16() % q);
+            let outs1 = ev1.eval(&c1, &en1.encode(&[x,y]));
+            assert_eq!(de1.decode(&outs1), vec![(x+y) % q]);
+
+            let inps2 = vec![outs1[0].clone(), en2.encode(&[0,w])[1].clone()];
+            let outs2 = ev2.eval_with_tweak_base(&c2, &inps2, base);
+            let res = de2.try_decode_with_cipher(&outs2, &TweakOffset::new(base)).unwrap();
+            assert_eq!(res, vec![(x as u32 * y as u32 * w as u32 % q as u32) as u16]);
+        }
+    }
+//}}}
+    #[test] // chain_tweaks {{{
+    fn chain_tweaks() {
+        use std::cell::RefCell;
+        use std::collections::HashSet;
+
+        struct Recorder(RefCell<Vec<u128>>);
+        impl Cipher for Recorder {
+            fn hash(&self, t: u128, x: u128) -> u128 {
+                self.0.borrow_mut().push(t);
+                AES.hash(t, x)
+            }
+            fn hash2(&self, t: u128, x: u128, y: u128) -> u128 {
+                self.0.borrow_mut().push(t);
+                AES.hash2(t, x, y)
+            }
+        }
+
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c1 = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let c2 = crate::circuit::random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+
+            let rec1 = Recorder(RefCell::new(Vec::new()));
+            let mut gb1 = Garbler::new(&c1).with_cipher(&rec1);
+            gb1.by_ref().for_each(drop);
+            gb1.decoder().unwrap();
+
+            let rec2 = Recorder(RefCell::new(Vec::new()));
+            let mut gb2 = Garbler::with_deltas(&c2, thread_rng(), gb1.deltas().clone())
+                .chain(&[], gb1.tweak_end())
+                .with_cipher(&rec2);
+            gb2.by_ref().for_each(drop);
+            gb2.decoder().unwrap();
+
+            let ts1: HashSet<u128> = rec1.0.borrow().iter().cloned().collect();
+            let ts2: HashSet<u128> = rec2.0.borrow().iter().cloned().collect();
+            assert!(!ts1.is_empty() && !ts2.is_empty());
+            assert!(ts1.is_disjoint(&ts2));
+            assert!(ts2.iter().all(|&t| t >= gb1.tweak_end() && t < gb2.tweak_end()));
+        }
+    }
 //}}}
     #[test] // serialize_evaluator {{{
     fn serialize_evaluator() {
@@ -681,5 +3407,52 @@ mod tests {
 
         assert_eq!(de, Decoder::from_bytes(&de.to_bytes()).unwrap());
     }
+//}}}
+    #[test] // deserialize_old_decoder {{{
+    fn deserialize_old_decoder() {
+        // a Decoder for one mod 2 output, serialized when it held only the tables
+        let mut old = Vec::new();
+        old.extend_from_slice(&1u64.to_le_bytes());
+        old.extend_from_slice(&2u64.to_le_bytes());
+        old.extend_from_slice(&5u128.to_le_bytes());
+        old.extend_from_slice(&7u128.to_le_bytes());
+
+        let de = Decoder::from_bytes(&old).unwrap();
+        assert_eq!(de, Decoder::new(vec![vec![5,7]], vec![2]));
+        assert!(de.output_names.is_empty());
+
+        // garbled outputs still decode after the round trip
+        let mut b = Builder::new();
+        let x = b.input(3);
+        b.output(x);
+        let c = b.finish();
+        let (en, de, ev) = garble(&c);
+        let old = bincode::serialize(&de.outputs).unwrap();
+        let de = Decoder::from_bytes(&old).unwrap();
+        assert_eq!(de.decode(&ev.eval(&c, &en.encode(&[2]))), vec![2]);
+
+        assert!(Decoder::from_bytes(&old[..old.len()-1]).is_err());
+        let mut long = old.clone();
+        long.push(0);
+        assert!(Decoder::from_bytes(&long).is_err());
+    }
+//}}}
+    #[test] // deserialize_truncated_decoder {{{
+    fn deserialize_truncated_decoder() {
+        let mut b = Builder::new();
+        let x = b.input(3);
+        let y = b.input(5);
+        b.output_named(x, "first");
+        b.output_named(y, "second");
+        let c = b.finish();
+        let (_, de, _) = garble(&c);
+
+        let bs = de.to_bytes();
+        assert_eq!(Decoder::from_bytes(&bs).unwrap(), de);
+        // cutting into the names must not load the decoder without them
+        for n in 1..20 {
+            assert!(Decoder::from_bytes(&bs[..bs.len()-n]).is_err());
+        }
+    }
 //}}}
 }
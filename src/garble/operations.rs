@@ -1,5 +1,6 @@
 //! The functions that do the actual garbling, used by the `Garbler` iterator.
 
+use crate::aes::Cipher;
 use crate::garble::GarbledGate;
 use crate::wire::Wire;
 use itertools::Itertools;
@@ -9,7 +10,7 @@ use std::collections::HashMap;
 ////////////////////////////////////////////////////////////////////////////////
 // garbler helper functions
 
-pub fn garble_output(X: &Wire, output_num: usize, deltas: &HashMap<u16,Wire>)
+pub fn garble_output(X: &Wire, output_num: usize, deltas: &HashMap<u16,Wire>, cipher: &dyn Cipher)
     -> Vec<u128>
 {
     let mut cts = Vec::new();
@@ -17,12 +18,12 @@ pub fn garble_output(X: &Wire, output_num: usize, deltas: &HashMap<u16,Wire>)
     let D = &deltas[&q];
     for k in 0..q {
         let t = output_tweak(output_num, k);
-        cts.push(X.plus(&D.cmul(k)).hash(t));
+        cts.push(X.plus(&D.cmul(k)).hash_with(cipher, t));
     }
     cts
 }
 
-pub fn garble_projection(A: &Wire, q_out: u16, tt: &[u16], gate_num: usize, deltas: &HashMap<u16,Wire>)
+pub fn garble_projection(A: &Wire, q_out: u16, tt: &[u16], gate_num: usize, deltas: &HashMap<u16,Wire>, cipher: &dyn Cipher)
     -> (Wire, Option<GarbledGate>)
 {
     let q_in = A.modulus();
@@ -44,7 +45,7 @@ pub fn garble_projection(A: &Wire, q_out: u16, tt: &[u16], gate_num: usize, delt
     //             .minus(&Dout.cmul(tt[((q_in - tao) % q_in) as usize]));
     let mut C = A.clone();
     C.plus_eq(&Din.cmul((q_in-tao) % q_in));
-    C = C.hashback(g, q_out);
+    C = C.hashback_with(cipher, g, q_out);
     C.plus_eq(&Dout.cmul((q_out - tt[((q_in - tao) % q_in) as usize]) % q_out));
 
     // precompute `let C_ = C.plus(&Dout.cmul(tt[x as usize]))`
@@ -67,7 +68,7 @@ pub fn garble_projection(A: &Wire, q_out: u16, tt: &[u16], gate_num: usize, delt
         let ix = (tao as usize + x as usize) % q_in as usize;
         if ix == 0 { continue }
 
-        let ct = A_.hash(g) ^ C_precomputed[tt[x as usize] as usize];
+        let ct = A_.hash_with(cipher, g) ^ C_precomputed[tt[x as usize] as usize];
         gate[ix - 1] = Some(ct);
     }
 
@@ -76,7 +77,7 @@ pub fn garble_projection(A: &Wire, q_out: u16, tt: &[u16], gate_num: usize, delt
     (C, Some(gate))
 }
 
-pub fn garble_yao(A: &Wire, B: &Wire, q: u16, tt: &[Vec<u16>], gate_num: usize, deltas: &HashMap<u16,Wire>)
+pub fn garble_yao(A: &Wire, B: &Wire, q: u16, tt: &[Vec<u16>], gate_num: usize, deltas: &HashMap<u16,Wire>, cipher: &dyn Cipher)
     -> (Wire, Option<GarbledGate>)
 {
     let xmod = A.modulus() as usize;
@@ -93,7 +94,7 @@ pub fn garble_yao(A: &Wire, B: &Wire, q: u16, tt: &[Vec<u16>], gate_num: usize,
     // we use the row reduction trick here
     let B_delta = &deltas[&(ymod as u16)];
     let C = A.minus(&deltas[&(xmod as u16)].cmul(A.color()))
-                .hashback2(&B.minus(&B_delta.cmul(B.color())), g, q)
+                .hashback2_with(cipher, &B.minus(&B_delta.cmul(B.color())), g, q)
                 .minus(&deltas[&q].cmul(sigma));
 
     for x in 0..xmod {
@@ -105,7 +106,7 @@ pub fn garble_yao(A: &Wire, B: &Wire, q: u16, tt: &[Vec<u16>], gate_num: usize,
             debug_assert_eq!(gate[ix-1], None);
             let B_ = B.plus(&deltas[&(ymod as u16)].cmul(y as u16));
             let C_ = C.plus(&deltas[&q].cmul(tt[x][y]));
-            let ct = A_.hash2(&B_,g) ^ C_.as_u128();
+            let ct = A_.hash2_with(cipher, &B_,g) ^ C_.as_u128();
             gate[ix-1] = Some(ct);
         }
     }
@@ -113,7 +114,30 @@ pub fn garble_yao(A: &Wire, B: &Wire, q: u16, tt: &[Vec<u16>], gate_num: usize,
     (C, Some(gate))
 }
 
-pub fn garble_half_gate<R: Rng>(A: &Wire, B: &Wire, gate_num: usize, deltas: &HashMap<u16,Wire>, rng: &mut R)
+/// Check a garbled Yao gate by evaluating it on every pair of input labels exactly as the
+/// evaluator does, including the fast path for the row where both colors are zero, and
+/// comparing against the output label `C + tt[x][y]*D` which the garbler intends. Panics
+/// on any disagreement.
+#[allow(clippy::too_many_arguments)]
+pub fn check_yao(A: &Wire, B: &Wire, q: u16, tt: &[Vec<u16>], gate_num: usize, deltas: &HashMap<u16,Wire>,
+                 cipher: &dyn Cipher, C: &Wire, gate: &[u128])
+{
+    let xmod = A.modulus();
+    let ymod = B.modulus();
+    for x in 0..xmod {
+        let A_ = A.plus(&deltas[&xmod].cmul(x));
+        for y in 0..ymod {
+            let B_ = B.plus(&deltas[&ymod].cmul(y));
+            let got = eval_yao(&A_, &B_, q, gate_num, gate, cipher);
+            let expected = C.plus(&deltas[&q].cmul(tt[x as usize][y as usize]));
+            assert!(got == expected,
+                "[check_yao] gate {} evaluates incorrectly on input ({},{}) with colors ({},{})",
+                gate_num, x, y, A_.color(), B_.color());
+        }
+    }
+}
+
+pub fn garble_half_gate<R: Rng>(A: &Wire, B: &Wire, gate_num: usize, deltas: &HashMap<u16,Wire>, cipher: &dyn Cipher, rng: &mut R)
     -> (Wire, Option<GarbledGate>)
 {
     let q = A.modulus();
@@ -142,7 +166,7 @@ pub fn garble_half_gate<R: Rng>(A: &Wire, B: &Wire, gate_num: usize, deltas: &Ha
                 B_.plus_eq(&Db);
             }
             let new_color = (r+b) % q;
-            let ct = (B_.hash(t) & 0xFFFF) ^ new_color as u128;
+            let ct = (B_.hash_with(cipher, t) & 0xFFFF) ^ new_color as u128;
             minitable[B_.color() as usize] = Some(ct);
         }
 
@@ -161,13 +185,13 @@ pub fn garble_half_gate<R: Rng>(A: &Wire, B: &Wire, gate_num: usize, deltas: &Ha
     // X = H(A+aD) + arD such that a + A.color == 0
     let alpha = (q - A.color()) % q; // alpha = -A.color
     let X = A.plus(&D.cmul(alpha))
-             .hashback(g,q)
+             .hashback_with(cipher, g,q)
              .plus(&D.cmul((alpha * r) % q));
 
     // Y = H(B + bD) + (b + r)A such that b + B.color == 0
     let beta = (qb - B.color()) % qb;
     let Y = B.plus(&Db.cmul(beta))
-             .hashback(g,q)
+             .hashback_with(cipher, g,q)
              .plus(&A.cmul((beta + r) % q));
 
     // precompute a lookup table of X.minus(&D_cmul[(a * r % q) as usize]).as_u128();
@@ -191,7 +215,7 @@ pub fn garble_half_gate<R: Rng>(A: &Wire, B: &Wire, gate_num: usize, deltas: &Ha
         // G = H(A+aD) ^ X+a(-r)D = H(A+aD) ^ X-arD
         if A_.color() != 0 {
             // let G = A_.hash(g) ^ X.minus(&D_cmul[(a * r % q) as usize]).as_u128();
-            let G = A_.hash(g) ^ X_cmul[((q - (a * r % q)) % q) as usize];
+            let G = A_.hash_with(cipher, g) ^ X_cmul[((q - (a * r % q)) % q) as usize];
             gate[A_.color() as usize - 1] = Some(G);
         }
     }
@@ -217,7 +241,7 @@ pub fn garble_half_gate<R: Rng>(A: &Wire, B: &Wire, gate_num: usize, deltas: &Ha
         // G = H(B+bD) + Y-(b+r)A
         if B_.color() != 0 {
             // let G = B_.hash(g) ^ Y.minus(&A_cmul[((b+r) % q) as usize]).as_u128();
-            let G = B_.hash(g) ^ Y_cmul[((q - ((b+r) % q)) % q) as usize];
+            let G = B_.hash_with(cipher, g) ^ Y_cmul[((q - ((b+r) % q)) % q) as usize];
             gate[q as usize - 1 + B_.color() as usize - 1] = Some(G);
         }
     }
@@ -226,6 +250,20 @@ pub fn garble_half_gate<R: Rng>(A: &Wire, B: &Wire, gate_num: usize, deltas: &Ha
     (X.plus(&Y), Some(gate)) // output zero wire
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// evaluator helper functions
+
+pub fn eval_yao(A: &Wire, B: &Wire, q: u16, gate_num: usize, gate: &[u128], cipher: &dyn Cipher) -> Wire {
+    if A.color() == 0 && B.color() == 0 {
+        // the row reduced out by the garbler
+        A.hashback2_with(cipher, B, tweak(gate_num), q)
+    } else {
+        let ix = A.color() as usize * B.modulus() as usize + B.color() as usize;
+        let ct = gate[ix - 1];
+        Wire::from_u128(ct ^ A.hash2_with(cipher, B, tweak(gate_num)), q)
+    }
+}
+
 pub fn encode_consts(consts: &[u16], const_wires: &[Wire], deltas: &HashMap<u16,Wire>) -> Vec<Wire> {
     debug_assert_eq!(consts.len(), const_wires.len(), "[encode_consts] not enough consts!");
     let mut xs = Vec::new();
@@ -0,0 +1,117 @@
+//! A length-prefixed framing for sending garbled gates one at a time, for instance over
+//! a socket while the `Garbler` is still running.
+//!
+//! The stream begins with a header frame holding the number of gate frames to follow and
+//! the constant wires. Each gate frame is a little-endian u32 count of ciphertexts
+//! followed by that many little-endian u128s.
+
+use crate::garble::GarbledGate;
+use crate::wire::Wire;
+use std::io::{Read, Write};
+
+pub fn write_header<W: Write>(mut w: W, ngates: usize, consts: &[Wire]) -> Result<(), failure::Error> {
+    write_u32(&mut w, ngates)?;
+    write_u32(&mut w, consts.len())?;
+    for (i, c) in consts.iter().enumerate() {
+        let block = c.to_block().ok_or_else(|| {
+            failure::format_err!("[stream.write_header] const {} does not fit in a block", i)
+        })?;
+        w.write_all(&c.modulus().to_le_bytes())?;
+        w.write_all(&block)?;
+    }
+    Ok(())
+}
+
+/// Read a header frame, returning the number of gates to follow and the constant wires.
+pub fn read_header<R: Read>(mut r: R) -> Result<(usize, Vec<Wire>), failure::Error> {
+    let ngates = read_u32(&mut r)?;
+    let nconsts = read_u32(&mut r)?;
+    let mut consts = Vec::new();
+    for _ in 0..nconsts {
+        let mut q = [0; 2];
+        r.read_exact(&mut q)?;
+        let mut block = [0; 16];
+        r.read_exact(&mut block)?;
+        consts.push(Wire::from_block(block, u16::from_le_bytes(q)));
+    }
+    Ok((ngates, consts))
+}
+
+pub fn write_gate<W: Write>(mut w: W, gate: &GarbledGate) -> Result<(), failure::Error> {
+    write_u32(&mut w, gate.len())?;
+    for ct in gate.iter() {
+        w.write_all(&ct.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_gate<R: Read>(mut r: R) -> Result<GarbledGate, failure::Error> {
+    let n = read_u32(&mut r)?;
+    let mut gate = Vec::new();
+    for _ in 0..n {
+        let mut ct = [0; 16];
+        r.read_exact(&mut ct)?;
+        gate.push(u128::from_le_bytes(ct));
+    }
+    Ok(gate)
+}
+
+fn write_u32<W: Write>(w: &mut W, x: usize) -> Result<(), failure::Error> {
+    if x > u32::MAX as usize {
+        return Err(failure::format_err!("[stream] length {} does not fit in a u32", x));
+    }
+    w.write_all(&(x as u32).to_le_bytes())?;
+    Ok(())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> Result<usize, failure::Error> {
+    let mut bs = [0; 4];
+    r.read_exact(&mut bs)?;
+    Ok(u32::from_le_bytes(bs) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::random_circuit;
+    use crate::garble::{Evaluator, Garbler};
+    use crate::util::RngExt;
+    use itertools::Itertools;
+    use rand::thread_rng;
+
+    #[test] // stream_gates {{{
+    fn stream_gates() {
+        let mut rng = thread_rng();
+        for _ in 0..16 {
+            let c = random_circuit(&mut rng, 4, 32, &[2,3,5,7]);
+            let mut gb = Garbler::new(&c);
+            let en = gb.encoder();
+
+            // the gates are written while the garbler is running, then the header
+            let mut gate_frames = Vec::new();
+            let mut ngates = 0;
+            for gate in gb.by_ref() {
+                write_gate(&mut gate_frames, &gate).unwrap();
+                ngates += 1;
+            }
+            let mut pipe = Vec::new();
+            write_header(&mut pipe, ngates, &gb.consts()).unwrap();
+            pipe.extend(gate_frames);
+            let de = gb.decoder().unwrap();
+
+            let mut r = &pipe[..];
+            let (n, consts) = read_header(&mut r).unwrap();
+            assert_eq!(n, ngates);
+            assert_eq!(consts, gb.consts());
+            let gates = (0..n).map(|_| read_gate(&mut r).unwrap()).collect_vec();
+            assert!(r.is_empty());
+            assert!(read_gate(&mut r).is_err());
+
+            let ev = Evaluator::new(gates, consts);
+            let inps = (0..c.ninputs()).map(|i| rng.gen_u16() % c.input_mod(i)).collect_vec();
+            let ys = ev.eval(&c, &en.encode(&inps));
+            assert_eq!(de.decode(&ys), c.eval(&inps));
+        }
+    }
+//}}}
+}
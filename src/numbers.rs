@@ -59,6 +59,13 @@ pub fn digits_per_u128(modulus: u16) -> usize {
     (128.0 / (modulus as f64).log2().ceil()).floor() as usize
 }
 
+/// The number of bits of entropy in a wire label of modulus `q`, given that only
+/// `digits_per_u128(q)` digits are packed into a u128. The weakest modulus is 5, at about
+/// 97.5 bits.
+pub fn effective_label_bits(q: u16) -> f64 {
+    digits_per_u128(q) as f64 * (q as f64).log2()
+}
+
 pub fn as_base_q_u128(x: u128, q: u16) -> Vec<u16> {
     as_base_q(x, q, digits_per_u128(q))
 }
@@ -323,6 +330,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn label_bits() {
+        assert_eq!(effective_label_bits(2), 128.0);
+        assert_eq!(effective_label_bits(4), 128.0);
+        assert_eq!(effective_label_bits(256), 128.0);
+        assert!((effective_label_bits(3) - 64.0 * 3f64.log2()).abs() < 1e-9);
+        assert!((effective_label_bits(5) - 42.0 * 5f64.log2()).abs() < 1e-9);
+        assert!((effective_label_bits(113) - 18.0 * 113f64.log2()).abs() < 1e-9);
+        for q in 2..=u16::MAX {
+            let bits = effective_label_bits(q);
+            assert!(bits >= effective_label_bits(5) && bits <= 128.0, "q={}", q);
+        }
+    }
+
     #[test]
     fn base_q_addition() {
         let mut rng = thread_rng();
@@ -1,4 +1,4 @@
-use crate::aes::AES;
+use crate::aes::{Aes, AES, Cipher};
 use crate::numbers;
 use crate::util::{self, RngExt};
 use rand::Rng;
@@ -50,6 +50,33 @@ impl Wire {
         }
     }
 
+    /// Equivalent to calling `from_u128` on each input, but the lookup tables for `q` are
+    /// fetched once for the whole batch rather than once per byte of every input.
+    pub fn batch_from_u128(inputs: &[u128], q: u16) -> Vec<Self> {
+        if q == 2 || q >= 256 || !base_conversion::lookup_defined_for_mod(q) {
+            return inputs.iter().map(|&x| Wire::from_u128(x, q)).collect();
+        }
+        let tables: Vec<(&[u16], usize)> = (0..16).map(|pos| {
+            (base_conversion::lookup_table_at_position(q, pos), base_conversion::num_digits_at_position(q, pos))
+        }).collect();
+        let ndigits = numbers::digits_per_u128(q);
+
+        inputs.iter().map(|&x| {
+            let bytes = util::u128_to_bytes(x);
+            let lookup = |pos: usize| {
+                let (tab, len) = tables[pos];
+                let start = len * bytes[pos] as usize;
+                &tab[start .. start + len]
+            };
+            let mut ds = lookup(15).to_vec();
+            for i in 0..15 {
+                numbers::base_q_add_eq(&mut ds, lookup(i), q);
+            }
+            ds.truncate(ndigits);
+            Wire::ModN { q, ds }
+        }).collect()
+    }
+
     pub fn as_u128(&self) -> u128 {
         match *self {
             Wire::Mod2 { val } => val,
@@ -57,6 +84,25 @@ impl Wire {
         }
     }
 
+    /// The label as a 16-byte block, for passing across an FFI boundary. Returns `None`
+    /// if the wire has more digits than can be packed into a u128.
+    pub fn to_block(&self) -> Option<[u8;16]> {
+        match *self {
+            Wire::Mod2 { val } => Some(util::u128_to_bytes(val)),
+            Wire::ModN { q, ref ds } => {
+                if ds.len() > numbers::digits_per_u128(q) {
+                    None
+                } else {
+                    Some(util::u128_to_bytes(self.as_u128()))
+                }
+            }
+        }
+    }
+
+    pub fn from_block(bytes: [u8;16], q: u16) -> Self {
+        Self::from_u128(util::bytes_to_u128(bytes), q)
+    }
+
     pub fn zero(modulus: u16) -> Self {
         match modulus {
             1 => panic!("[wire::zero] mod 1 not allowed!"),
@@ -106,6 +152,21 @@ impl Wire {
         }
     }
 
+    /// A copy of this wire with its color digit set to `c` and every other digit unchanged,
+    /// for tests that need to drive point-and-permute down a particular path.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_color(&self, c: u16) -> Self {
+        assert!(c < self.modulus(), "[wire.with_color] color {} too large for modulus {}", c, self.modulus());
+        match *self {
+            Wire::Mod2 { val } => Wire::Mod2 { val: (val & !1) | c as u128 },
+            Wire::ModN { q, ref ds } => {
+                let mut ds = ds.clone();
+                ds[0] = c;
+                Wire::ModN { q, ds }
+            }
+        }
+    }
+
     pub fn plus(&self, other: &Self) -> Self {
         match (self, other) {
             (&Wire::Mod2 { val: x }, &Wire::Mod2 { val: y }) => {
@@ -212,6 +273,62 @@ impl Wire {
         }
     }
 
+    /// Negate the wire if `sel` is true, otherwise return a copy of it.
+    pub fn conditional_negate(&self, sel: bool) -> Wire {
+        if sel {
+            self.negate()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Whether the two wires are equal in every digit except the color digit (bit 0 for
+    /// `Mod2`). Wires of different moduli are never equal. Note that the labels `X` and
+    /// `X.plus(&delta)` for two values of the same wire are generally *not* equal in this
+    /// sense, since a delta is random in all of its digits and only its color digit is
+    /// fixed (to 1); equality here holds only for labels which differ solely in color.
+    /// A `ModN` wire with no digits has no color, and is never equal to anything.
+    pub fn eq_ignoring_color(&self, other: &Wire) -> bool {
+        match (self, other) {
+            (Wire::Mod2 { val: x }, Wire::Mod2 { val: y }) => (x ^ y) >> 1 == 0,
+            (Wire::ModN { q: xmod, ds: xs }, Wire::ModN { q: ymod, ds: ys }) => {
+                xmod == ymod && !xs.is_empty() && xs.len() == ys.len()
+                    && xs.iter().skip(1).eq(ys.iter().skip(1))
+            }
+            _ => false,
+        }
+    }
+
+    /// SPDZ-style information-theoretic MAC of this wire under the global MAC key `key`:
+    /// the digitwise product `self * key` in the label space. The MAC is linear, so
+    /// `x.plus(&y).mac(key) == x.mac(key).plus(&y.mac(key))`, and parties holding additive
+    /// shares of a value and of its MAC hold authenticated shares of it. Forging a MAC for
+    /// a tampered digit requires guessing the corresponding digit of `key`, so for prime
+    /// `q` and a key with nonzero digits each tampered digit is caught except with
+    /// probability `1/q`. For `Mod2` wires each key bit only detects tampering with
+    /// probability 1/2.
+    pub fn mac(&self, key: &Wire) -> Wire {
+        match (self, key) {
+            (Wire::Mod2 { val: x }, Wire::Mod2 { val: k }) => Wire::Mod2 { val: x & k },
+
+            (Wire::ModN { q, ds: xs }, Wire::ModN { q: kmod, ds: ks }) => {
+                assert_eq!(q, kmod, "[wire::mac] unequal moduli!");
+                debug_assert_eq!(xs.len(), ks.len());
+                let ds = xs.iter().zip(ks.iter()).map(|(&x,&k)| {
+                    (x as u32 * k as u32 % *q as u32) as u16
+                }).collect();
+                Wire::ModN { q: *q, ds }
+            }
+
+            _ => panic!("[wire::mac] unequal moduli!"),
+        }
+    }
+
+    /// Check that `mac` is the MAC of this wire under `key`.
+    pub fn verify_mac(&self, key: &Wire, mac: &Wire) -> bool {
+        self.mac(key) == *mac
+    }
+
     pub fn minus(&self, other: &Wire) -> Wire {
         match *self {
             Wire::Mod2 { .. } => self.plus(&other),
@@ -231,20 +348,68 @@ impl Wire {
     }
 
     pub fn hash(&self, tweak: u128) -> u128 {
-        AES.hash(tweak, self.as_u128())
+        self.hash_with(&AES, tweak)
     }
 
     // hash to u128 and back to Wire
     pub fn hashback(&self, tweak: u128, new_mod: u16) -> Wire {
-        Self::from_u128(self.hash(tweak), new_mod)
+        self.hashback_with(&AES, tweak, new_mod)
     }
 
     pub fn hash2(&self, other: &Wire, tweak: u128) -> u128 {
-        AES.hash2(tweak, self.as_u128(), other.as_u128())
+        self.hash2_with(&AES, other, tweak)
     }
 
     pub fn hashback2(&self, other: &Wire, tweak: u128, new_modulus: u16) -> Wire {
-        Self::from_u128(self.hash2(other, tweak), new_modulus)
+        self.hashback2_with(&AES, other, tweak, new_modulus)
+    }
+
+    pub fn hash_with(&self, cipher: &dyn Cipher, tweak: u128) -> u128 {
+        cipher.hash(tweak, self.as_u128())
+    }
+
+    pub fn hashback_with(&self, cipher: &dyn Cipher, tweak: u128, new_mod: u16) -> Wire {
+        Self::from_u128(self.hash_with(cipher, tweak), new_mod)
+    }
+
+    pub fn hash2_with(&self, cipher: &dyn Cipher, other: &Wire, tweak: u128) -> u128 {
+        cipher.hash2(tweak, self.as_u128(), other.as_u128())
+    }
+
+    pub fn hashback2_with(&self, cipher: &dyn Cipher, other: &Wire, tweak: u128, new_modulus: u16) -> Wire {
+        Self::from_u128(self.hash2_with(cipher, other, tweak), new_modulus)
+    }
+
+    /// Derive a new label of modulus `q` from this one, as a key derivation function rather
+    /// than for evaluating gates. This label keys AES, which is applied to `context` in
+    /// Davies-Meyer style, so labels derived with different `context`s, or from different
+    /// labels, are pseudo-independent, and unrelated to the hashes used for garbling.
+    pub fn derive(&self, context: u128, q: u16) -> Wire {
+        Self::from_u128(Aes::new(self.as_u128()).eval_u128(context) ^ context, q)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// operators
+
+impl std::ops::Add for &Wire {
+    type Output = Wire;
+    fn add(self, other: &Wire) -> Wire {
+        self.plus(other)
+    }
+}
+
+impl std::ops::Sub for &Wire {
+    type Output = Wire;
+    fn sub(self, other: &Wire) -> Wire {
+        self.minus(other)
+    }
+}
+
+impl std::ops::Mul<u16> for &Wire {
+    type Output = Wire;
+    fn mul(self, c: u16) -> Wire {
+        self.cmul(c)
     }
 }
 
@@ -283,6 +448,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn blocks() {
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let x = Wire::rand(&mut rng, 2);
+            assert_eq!(Wire::from_block(x.to_block().unwrap(), 2), x);
+
+            let q = 3 + (rng.gen_u16() % 110);
+            let y = Wire::rand(&mut rng, q);
+            assert_eq!(Wire::from_block(y.to_block().unwrap(), q), y);
+        }
+        let too_long = Wire::ModN { q: 3, ds: vec![0; numbers::digits_per_u128(3) + 1] };
+        assert_eq!(too_long.to_block(), None);
+    }
+
+    #[test]
+    fn with_color() {
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let q = 2 + (rng.gen_u16() % 111);
+            let w = Wire::rand(&mut rng, q);
+            let c = rng.gen_u16() % q;
+            let x = w.with_color(c);
+            assert_eq!(x.color(), c);
+            assert_eq!(x.modulus(), q);
+            assert_eq!(x.digits()[1..], w.digits()[1..]);
+            assert_eq!(x.with_color(w.color()), w);
+        }
+    }
+
+    #[test]
+    fn batch_from_u128() {
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let q = 2 + (rng.gen_u16() % 300);
+            let xs = (0..1 + rng.gen_usize() % 16).map(|_| rng.gen_u128()).collect::<Vec<_>>();
+            let ws = xs.iter().map(|&x| Wire::from_u128(x, q)).collect::<Vec<_>>();
+            assert_eq!(Wire::batch_from_u128(&xs, q), ws);
+        }
+        assert!(Wire::batch_from_u128(&[], 17).is_empty());
+    }
+
     #[test]
     fn base_conversion_lookup_method() {
         let ref mut rng = thread_rng();
@@ -325,6 +532,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn conditional_negation() {
+        let mut rng = thread_rng();
+        for _ in 0..1000 {
+            let q = rng.gen_modulus();
+            let x = Wire::rand(&mut rng, q);
+            assert_eq!(x.conditional_negate(true), x.negate());
+            assert_eq!(x.conditional_negate(false), x);
+        }
+    }
+
+    #[test]
+    fn eq_ignoring_color() {
+        let x = Wire::Mod2 { val: 0b1010 };
+        assert!(x.eq_ignoring_color(&Wire::Mod2 { val: 0b1011 }));
+        assert!(!x.eq_ignoring_color(&Wire::Mod2 { val: 0b1000 }));
+
+        let y = Wire::ModN { q: 5, ds: vec![0,1,2,3] };
+        assert!(y.eq_ignoring_color(&Wire::ModN { q: 5, ds: vec![4,1,2,3] }));
+        assert!(!y.eq_ignoring_color(&Wire::ModN { q: 5, ds: vec![0,1,2,4] }));
+        assert!(!y.eq_ignoring_color(&Wire::ModN { q: 7, ds: vec![0,1,2,3] }));
+        assert!(!y.eq_ignoring_color(&x));
+
+        let empty = Wire::ModN { q: 5, ds: vec![] };
+        assert!(!empty.eq_ignoring_color(&empty));
+        assert!(!empty.eq_ignoring_color(&y));
+        assert!(!y.eq_ignoring_color(&empty));
+
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let q = rng.gen_modulus();
+            let x = Wire::rand(&mut rng, q);
+            let delta = Wire::rand_delta(&mut rng, q);
+            assert!(x.eq_ignoring_color(&x));
+            // a label and its delta-shift differ in color, and almost surely elsewhere too
+            assert!(x != x.plus(&delta));
+            assert!(x.color() != x.plus(&delta).color());
+            assert!(!x.eq_ignoring_color(&x.plus(&delta)));
+        }
+    }
+
+    #[test]
+    fn mac() {
+        let mut rng = thread_rng();
+        for _ in 0..1000 {
+            let q = rng.gen_prime();
+            let n = numbers::digits_per_u128(q);
+            // a key with no zero digits, so that tampering with any digit is detected
+            let key = if q == 2 {
+                Wire::Mod2 { val: !0 }
+            } else {
+                Wire::ModN { q, ds: (0..n).map(|_| 1 + rng.gen_u16() % (q-1)).collect() }
+            };
+            let x = Wire::rand(&mut rng, q);
+            let y = Wire::rand(&mut rng, q);
+            let m = x.mac(&key);
+            assert!(x.verify_mac(&key, &m));
+            assert_eq!(x.plus(&y).mac(&key), m.plus(&y.mac(&key)));
+
+            let mut d = Wire::zero(q);
+            match d {
+                Wire::Mod2 { ref mut val } => *val = 1 << (rng.gen_usize() % 128),
+                Wire::ModN { ref mut ds, .. } => ds[rng.gen_usize() % n] = 1 + rng.gen_u16() % (q-1),
+            }
+            assert!(!x.plus(&d).verify_mac(&key, &m));
+            assert!(!x.verify_mac(&key, &m.plus(&d)));
+        }
+    }
+
     #[test]
     fn zero() {
         let mut rng = thread_rng();
@@ -390,6 +666,63 @@ mod tests {
         }
     }
 
+    #[test]
+    fn operators() {
+        let mut rng = thread_rng();
+        for _ in 0..1024 {
+            let q = rng.gen_modulus();
+            let x = Wire::rand(&mut rng, q);
+            let y = Wire::rand(&mut rng, q);
+            let z = Wire::rand(&mut rng, q);
+            let c = rng.gen_u16();
+            assert_eq!(&x + &y, x.plus(&y));
+            assert_eq!(&x - &y, x.minus(&y));
+            assert_eq!(&x * c, x.cmul(c));
+            assert_eq!(&(&x + &y) - &z, x.plus(&y).minus(&z));
+        }
+    }
+
+    #[test]
+    fn derive() {
+        let mut rng = thread_rng();
+        for _ in 0..64 {
+            let q = rng.gen_modulus();
+            let p = rng.gen_modulus();
+            let x = Wire::rand(&mut rng, q);
+            let c = rng.gen_u128();
+            let y = x.derive(c, p);
+            assert_eq!(y.modulus(), p);
+            assert_eq!(x.derive(c, p), y);
+            assert_ne!(x.derive(c ^ 1, p), y);
+            assert_ne!(x.derive(c, p).as_u128() ^ x.derive(c ^ 1, p).as_u128(),
+                       x.derive(c ^ 2, p).as_u128() ^ x.derive(c ^ 3, p).as_u128());
+            assert_ne!(Wire::rand(&mut rng, q).derive(c, p), y);
+        }
+
+        // derived labels are not a fixed offset from gate hashes of the same AES input
+        for _ in 0..64 {
+            let c = rng.gen_u128();
+            let t = rng.gen_u128();
+            let offset = |x: &Wire| {
+                let z = Wire::from_u128(x.as_u128() ^ c, 2);
+                x.derive(c, 2).as_u128() ^ z.hash(t)
+            };
+            let x = Wire::rand(&mut rng, 2);
+            let y = Wire::from_u128(x.as_u128() ^ 1, 2);
+            assert_ne!(offset(&x), offset(&y));
+        }
+
+        // shifting the label cannot be undone by shifting the context
+        for _ in 0..64 {
+            let q = rng.gen_modulus();
+            let p = rng.gen_modulus();
+            let x = Wire::rand(&mut rng, q);
+            let d = Wire::rand_delta(&mut rng, q);
+            let c = rng.gen_u128();
+            assert_ne!(x.derive(c, p), x.plus(&d).derive(c ^ d.as_u128(), p));
+        }
+    }
+
     #[test]
     fn ndigits_correct() {
         let mut rng = thread_rng();